@@ -1,42 +1,829 @@
-use super::verify_input_file;
-use std::fmt;
-use std::str::FromStr;
+use super::{verify_input_file, verify_input_pattern, verify_path};
 use anyhow::anyhow;
-use clap::Parser;
-
+use chrono::NaiveDate;
+use chrono::format::{Item, StrftimeItems};
+use clap::{ArgAction, ArgGroup, Parser};
+use encoding_rs::Encoding;
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone)]
 pub enum OutputFormat {
     Json,
+    Jsonl,
     Yaml,
+    Table,
+    Toml,
+    Xml,
+    Markdown,
+    Html,
+    Csv,
+    Parquet,
+    Arrow,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum InputFormat {
+    Csv,
+    Json,
+    Jsonl,
+    Yaml,
+}
+
+/// Codec of the Parquet and Arrow IPC outputs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum QuoteStyle {
+    Always,
+    Necessary,
+    NonNumeric,
+    Never,
+}
+/// A `--sort-by` key: a column, its direction and how values compare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: String,
+    pub descending: bool,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortOrder {
+    Lexical,
+    Numeric,
+    Natural,
+}
+
+/// What happens to records that cannot be read or converted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnError {
+    Skip,
+    Reject,
+    Fail,
+}
+
+/// A `--mask` rule: how the values of a column are scrubbed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskRule {
+    pub column: String,
+    pub mask: Mask,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mask {
+    /// Replace the value with a fixed text
+    Redact(String),
+    /// Keep the first N characters
+    Truncate(usize),
+    /// Replace the value with made-up data of the same kind
+    Fake(FakeKind),
+    /// Replace the value with its keyed BLAKE3 hash
+    Hash,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FakeKind {
+    Name,
+    Email,
+    Phone,
+}
+
+/// Which record `--dedup` keeps when several share a key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Keep {
+    First,
+    Last,
+}
+
+/// Options of `rcli csv`. The ones about reading the input, such as its
+/// dialect and what happens to malformed records, are global; the others come
+/// in groups that each subcommand takes only when it uses them. Without a
+/// subcommand the records are converted and every group applies.
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct CsvOpts {
-    #[arg(short, long,value_parser=verify_input_file)]
-    pub input: String,
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    #[command(flatten)]
+    pub output: OutputOpts,
+
+    /// Where derived outputs go; next to each input by default
+    #[arg(long, value_parser=verify_path)]
+    pub out_dir: Option<PathBuf>,
+
+    /// Input format (csv, json, jsonl, yaml); guessed from the file extension by default
+    #[arg(long, value_parser=parse_input_format, global = true)]
+    pub from: Option<InputFormat>,
+
+    /// Input encoding, e.g. utf-16le or windows-1252; detected by default
+    #[arg(long, value_parser=parse_encoding, global = true)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// Separator used to join array values when flattening JSON/YAML input
+    #[arg(long, default_value = "; ", global = true)]
+    pub join_sep: String,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    /// What to do with malformed records (skip, reject, fail)
+    #[arg(long, value_parser=parse_on_error, default_value = "fail", global = true)]
+    pub on_error: OnError,
+
+    /// With --on-error reject: where malformed records go, each prefixed with
+    /// its input file, line number and the error
+    #[arg(long, default_value = "rejected.csv", global = true)]
+    pub reject_file: String,
+
+    #[command(flatten)]
+    pub conversion: ConversionOpts,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct InputOpts {
+    /// Input file, directory or glob pattern, or `-` for stdin; repeat to
+    /// convert several
+    #[arg(short = 'i', long = "input", value_name = "INPUT", value_parser=verify_input_pattern, required = true)]
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct OutputOpts {
+    /// Output file, or `-` for stdout; derived from the input name by default
+    #[arg(short = 'o', long = "output", value_name = "OUTPUT")]
+    pub path: Option<String>,
+
+    /// Overwrite existing outputs with derived names
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Everything a conversion applies to the records, from picking them to
+/// writing them out.
+#[derive(Debug, Clone, Parser)]
+pub struct ConversionOpts {
+    #[command(flatten)]
+    pub records: RecordOpts,
+
+    #[command(flatten)]
+    pub limit: LimitOpts,
+
+    #[command(flatten)]
+    pub convert: ConvertOpts,
+
+    #[command(flatten)]
+    pub typing: TypeOpts,
+
+    #[command(flatten)]
+    pub dates: DateOpts,
+
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+/// Which records are read, in what order, and which of their columns.
+#[derive(Debug, Clone, Parser)]
+pub struct RecordOpts {
+    /// Only keep records matching an expression, e.g. 'Creator == "John Doe" && Notes != ""'
+    #[arg(long = "where")]
+    pub filter: Option<String>,
+
+    /// Sort by columns, e.g. --sort-by 'Date:desc,Accession No:num'
+    /// (modifiers: asc, desc, lex, num, nat)
+    #[arg(long, value_delimiter = ',', value_parser=parse_sort_key)]
+    pub sort_by: Vec<SortKey>,
+
+    /// Records sorted in memory before spilling to a temporary file; this
+    /// counts records whatever their size, so lower it for very wide rows
+    #[arg(long, default_value_t = 100_000)]
+    pub sort_buffer: usize,
+
+    /// Drop records repeating these key columns; all columns when none are given
+    #[arg(long, num_args = 0.., value_delimiter = ',')]
+    pub dedup: Option<Vec<String>>,
+
+    /// Which duplicate --dedup keeps (first, last)
+    #[arg(long, value_parser=parse_keep, default_value = "first")]
+    pub keep: Keep,
+
+    /// Scrub a column before output, e.g. --mask Email=hash --mask Notes=redact
+    /// (redact[:TEXT], truncate:N, fake:name|email|phone, hash)
+    #[arg(long, value_parser=parse_mask_rule)]
+    pub mask: Vec<MaskRule>,
+
+    /// Key for --mask hash and fake, as written by `rcli text generate`;
+    /// without it fakes are keyed by a random key and differ between runs
+    #[arg(long, value_parser=verify_input_file)]
+    pub mask_key: Option<String>,
+
+    /// Columns to keep, in output order, by name or 1-based index
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    /// Columns to drop, by name or 1-based index
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Rename a column, e.g. --rename 'Accession No=accession'
+    #[arg(long, value_parser=parse_rename)]
+    pub rename: Vec<(String, String)>,
+}
+
+/// Which of the emitted records are kept.
+#[derive(Debug, Clone, Parser)]
+pub struct LimitOpts {
+    /// Only emit the first N records
+    #[arg(long)]
+    pub head: Option<usize>,
+
+    /// Only emit the last N records
+    #[arg(long)]
+    pub tail: Option<usize>,
+}
+
+/// How the fields of a record become JSON values.
+#[derive(Debug, Clone, Parser)]
+pub struct ConvertOpts {
+    /// Infer int, float, bool and null values per column
+    #[arg(long)]
+    pub infer: bool,
+
+    /// Split a column into an array, e.g. --array 'Subject=;'
+    #[arg(long = "array", value_parser=parse_array_column)]
+    pub arrays: Vec<(String, String)>,
+
+    /// Build nested objects and arrays from headers like address.city or tags[0]
+    #[arg(long)]
+    pub nest: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct TypeOpts {
+    /// Force the type of a column, e.g. --type id=int (int, float, bool, date, string)
+    #[arg(long = "type", value_parser=parse_column_type)]
+    pub types: Vec<(String, ColumnType)>,
+
+    /// strftime format for date columns; ISO-8601 by default
+    #[arg(long, value_parser=parse_date_format)]
+    pub date_format: Option<String>,
+}
+
+/// How ambiguous dates are read.
+#[derive(Debug, Clone, Parser)]
+pub struct DateOpts {
+    /// Two-digit years below this are 20xx, the others 19xx
+    #[arg(long, default_value_t = 50)]
+    pub year_pivot: u32,
+
+    /// Read ambiguous numeric dates as day/month/year instead of month/day/year
+    #[arg(long)]
+    pub day_first: bool,
+}
+
+#[derive(Debug, Parser)]
+pub enum CsvSubCommand {
+    #[command(
+        name = "agg",
+        about = "Group records by key columns and aggregate them"
+    )]
+    Agg(AggOpts),
+    #[command(
+        name = "stats",
+        about = "Profile every column: type, empties, distinct values, range"
+    )]
+    Stats(StatsOpts),
+    #[command(name = "validate", about = "Check records against a YAML schema")]
+    Validate(ValidateOpts),
+    #[command(name = "join", about = "Join two files on key columns")]
+    Join(JoinOpts),
+    #[command(
+        name = "diff",
+        about = "Report rows added, removed or changed between two files"
+    )]
+    Diff(DiffOpts),
+    #[command(
+        name = "split",
+        about = "Cut a file into parts by rows, bytes or column value"
+    )]
+    Split(SplitOpts),
+    #[command(name = "merge", about = "Concatenate files, aligning columns by name")]
+    Merge(MergeOpts),
+    #[command(
+        name = "sql",
+        about = "Load the inputs into SQLite and run a query over them"
+    )]
+    Sql(SqlOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct AggOpts {
+    /// Columns to group by, by name or 1-based index; one group when omitted
+    #[arg(long, value_delimiter = ',')]
+    pub by: Vec<String>,
+
+    /// Aggregates per group, e.g. -a count,sum:Price,median:Price
+    /// (count, sum, min, max, mean, median, distinct); count by default
+    #[arg(short, long = "aggregate", value_delimiter = ',', value_parser=parse_aggregate)]
+    pub aggregates: Vec<Aggregate>,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    #[command(flatten)]
+    pub output: OutputOpts,
+
+    #[command(flatten)]
+    pub records: RecordOpts,
+
+    #[command(flatten)]
+    pub limit: LimitOpts,
+
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct StatsOpts {
+    /// Number of most frequent values listed per column
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    /// Output file, or `-` for stdout (the default)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub records: RecordOpts,
+
+    #[command(flatten)]
+    pub dates: DateOpts,
+
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct ValidateOpts {
+    /// YAML file with the rules for each column
+    #[arg(long, value_parser=verify_input_file)]
+    pub schema: String,
+
+    /// How violations are reported (human, json)
+    #[arg(long, value_parser=parse_report_format, default_value = "human")]
+    pub report: ReportFormat,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    /// Output file, or `-` for stdout (the default)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub dates: DateOpts,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Parser)]
+pub struct JoinOpts {
+    #[arg(value_parser=verify_input_file)]
+    pub left: String,
+
+    #[arg(value_parser=verify_input_file)]
+    pub right: String,
+
+    /// Key columns, e.g. --on Identifier or --on 'Identifier=Object ID' when
+    /// the names differ
+    #[arg(long, required = true, value_delimiter = ',', value_parser=parse_join_key)]
+    pub on: Vec<(String, String)>,
+
+    /// Join type (inner, left, right, full)
+    #[arg(long, value_parser=parse_join_kind, default_value = "inner")]
+    pub how: JoinKind,
+
+    /// Prefix for left columns whose name also appears on the right
+    #[arg(long, default_value = "left_")]
+    pub left_prefix: String,
+
+    /// Prefix for right columns whose name also appears on the left
+    #[arg(long, default_value = "right_")]
+    pub right_prefix: String,
+
+    #[command(flatten)]
+    pub output: OutputOpts,
+
+    #[command(flatten)]
+    pub conversion: ConversionOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffOpts {
+    #[arg(value_parser=verify_input_file)]
+    pub old: String,
 
+    #[arg(value_parser=verify_input_file)]
+    pub new: String,
+
+    /// Columns identifying a row in both files
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub key: Vec<String>,
+
+    /// Output file, or `-` for stdout (the default)
     #[arg(short, long)]
     pub output: Option<String>,
 
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+/// Every input becomes a table named after its file, with the column types
+/// inferred from its values; a single input can also be queried as `t`.
+#[derive(Debug, Parser)]
+pub struct SqlOpts {
+    /// Query to run, e.g. "SELECT Creator, count(*) FROM t GROUP BY 1"
+    pub query: String,
+
+    /// SQLite database file to load the tables into and keep; in memory by default
+    #[arg(long)]
+    pub db: Option<PathBuf>,
+
+    /// Replace tables the --db database already has
+    #[arg(long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    /// Output file, or `-` for stdout (the default)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub typing: TypeOpts,
+
+    #[command(flatten)]
+    pub dates: DateOpts,
+
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+/// Parts are CSV files that each repeat the header.
+#[derive(Debug, Parser)]
+#[command(group(ArgGroup::new("mode").required(true).args(["rows", "bytes", "by"])))]
+pub struct SplitOpts {
+    /// Records per part
+    #[arg(long)]
+    pub rows: Option<usize>,
+
+    /// Maximum size of a part, e.g. 500K or 10M
+    #[arg(long, value_parser=parse_size)]
+    pub bytes: Option<u64>,
+
+    /// One part per distinct value of this column
+    #[arg(long)]
+    pub by: Option<String>,
+
+    /// Directory the parts are written to
+    #[arg(long, value_parser=verify_path, default_value = ".")]
+    pub out_dir: PathBuf,
+
+    /// Name prefix of the parts; the input's file name by default
+    #[arg(long)]
+    pub prefix: Option<String>,
+
+    #[command(flatten)]
+    pub input: InputOpts,
+
+    #[command(flatten)]
+    pub records: RecordOpts,
+
+    #[command(flatten)]
+    pub csv: CsvWriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct MergeOpts {
+    /// Files to concatenate; the columns of all of them are kept, in the
+    /// order they are first seen
+    #[arg(required = true, value_parser=verify_input_file)]
+    pub files: Vec<String>,
+
+    #[command(flatten)]
+    pub output: OutputOpts,
+
+    #[command(flatten)]
+    pub conversion: ConversionOpts,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// An aggregate function, and the column it reads unless it counts records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub func: AggFunc,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    Median,
+    Distinct,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    Date,
+    String,
+}
+
+/// Output format and the options that only apply to some formats.
+#[derive(Debug, Clone, Parser)]
+pub struct WriterOpts {
     #[arg(long,value_parser=parse_format, default_value ="json")]
     pub format: OutputFormat,
 
-    #[arg(short, long, default_value_t = ',')]
-    delimiter: char,
+    /// Table: maximum width of a column
+    #[arg(long, default_value_t = 40)]
+    pub max_width: usize,
 
-    #[arg(long, default_value_t = true)]
-    header: bool,
+    /// Table: wrap long cells instead of truncating them
+    #[arg(long)]
+    pub wrap: bool,
+
+    /// Table: number of rows laid out together
+    #[arg(long, default_value_t = 100)]
+    pub page_size: usize,
+
+    /// Table: do not pipe terminal output through $PAGER
+    #[arg(long)]
+    pub no_pager: bool,
+
+    #[command(flatten)]
+    pub csv: CsvWriterOpts,
+
+    /// Parquet/Arrow: compression codec (none, snappy, gzip, zstd, lz4);
+    /// snappy for Parquet and none for Arrow by default
+    #[arg(long, value_parser=parse_compression)]
+    pub compression: Option<Compression>,
+}
+
+/// How emitted CSV is laid out.
+#[derive(Debug, Clone, Parser)]
+pub struct CsvWriterOpts {
+    /// CSV: field delimiter of the emitted file
+    #[arg(long, value_parser=parse_ascii_char, default_value = ",")]
+    pub out_delimiter: u8,
+
+    /// CSV: when to quote fields (always, necessary, non-numeric, never)
+    #[arg(long, value_parser=parse_quote_style, default_value = "necessary")]
+    pub quote_style: QuoteStyle,
+}
+
+/// How the input CSV is laid out: separators, quoting and header handling.
+#[derive(Debug, Clone, Parser)]
+pub struct CsvDialect {
+    #[arg(short, long, value_parser=parse_ascii_char, default_value = ",", global = true)]
+    pub delimiter: u8,
+
+    #[arg(long, value_parser=parse_ascii_char, default_value = "\"", global = true)]
+    pub quote: u8,
+
+    /// Escape character used instead of doubled quotes
+    #[arg(long, value_parser=parse_ascii_char, global = true)]
+    pub escape: Option<u8>,
+
+    /// Lines starting with this character are skipped
+    #[arg(long, value_parser=parse_ascii_char, global = true)]
+    pub comment: Option<u8>,
+
+    /// Trim whitespace around headers and fields
+    #[arg(long, global = true)]
+    pub trim: bool,
+
+    /// Allow records with a varying number of fields
+    #[arg(long, global = true)]
+    pub flexible: bool,
+
+    /// Whether the first row is a header row
+    #[arg(long, default_value_t = true, action = ArgAction::Set, global = true)]
+    pub header: bool,
+
+    /// Column names to use instead of the header row, e.g. --columns id,name,date
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub columns: Vec<String>,
 }
 
 fn parse_format(input: &str) -> Result<OutputFormat, anyhow::Error> {
     input.parse::<OutputFormat>()
 }
 
+fn parse_input_format(s: &str) -> Result<InputFormat, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_encoding(s: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(s.as_bytes()).ok_or_else(|| anyhow!("Unknown encoding: {}", s))
+}
+
+fn parse_quote_style(s: &str) -> Result<QuoteStyle, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_compression(s: &str) -> Result<Compression, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_rename(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err(anyhow!("Expected OLD=NEW, got: {}", s)),
+    }
+}
+
+fn parse_array_column(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((name, sep)) if !sep.is_empty() => Ok((name.to_string(), sep.to_string())),
+        _ => Err(anyhow!("Expected COLUMN=SEPARATOR, got: {}", s)),
+    }
+}
+
+fn parse_column_type(s: &str) -> Result<(String, ColumnType), anyhow::Error> {
+    let (name, ty) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("Expected COLUMN=TYPE, got: {}", s))?;
+    Ok((name.to_string(), ty.parse()?))
+}
+
+fn parse_mask_rule(s: &str) -> Result<MaskRule, anyhow::Error> {
+    // the first '=' followed by a valid rule ends the column name, so both
+    // the name and a redaction text may contain '='
+    for (i, _) in s.match_indices('=') {
+        if let (column, Ok(mask)) = (&s[..i], s[i + 1..].parse())
+            && !column.is_empty()
+        {
+            return Ok(MaskRule {
+                column: column.to_string(),
+                mask,
+            });
+        }
+    }
+    Err(anyhow!(
+        "Expected COLUMN=RULE (redact[:TEXT], truncate:N, fake:KIND, hash), got: {}",
+        s
+    ))
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey, anyhow::Error> {
+    let mut key = SortKey {
+        column: s.to_string(),
+        descending: false,
+        order: SortOrder::Lexical,
+    };
+    // modifiers are peeled off the end so column names may contain ':'
+    while let Some((column, modifier)) = key.column.rsplit_once(':') {
+        match modifier {
+            "asc" => key.descending = false,
+            "desc" => key.descending = true,
+            _ => match modifier.parse() {
+                Ok(order) => key.order = order,
+                Err(_) => break,
+            },
+        }
+        key.column = column.to_string();
+    }
+    if key.column.is_empty() {
+        return Err(anyhow!("Expected COLUMN[:desc][:num|:nat], got: {}", s));
+    }
+    Ok(key)
+}
+
+fn parse_aggregate(s: &str) -> Result<Aggregate, anyhow::Error> {
+    let (func, column) = match s.split_once(':') {
+        Some((func, column)) if !column.is_empty() => (func, Some(column.to_string())),
+        Some(_) => return Err(anyhow!("Expected FUNC:COLUMN, got: {}", s)),
+        None => (s, None),
+    };
+    let func: AggFunc = func.parse()?;
+    if column.is_none() && func != AggFunc::Count {
+        return Err(anyhow!("{} needs a column, e.g. {}:Price", func, func));
+    }
+    Ok(Aggregate { func, column })
+}
+
+fn parse_report_format(s: &str) -> Result<ReportFormat, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_on_error(s: &str) -> Result<OnError, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_join_key(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((left, right)) if !left.is_empty() && !right.is_empty() => {
+            Ok((left.to_string(), right.to_string()))
+        }
+        None if !s.is_empty() => Ok((s.to_string(), s.to_string())),
+        _ => Err(anyhow!("Expected COLUMN or LEFT=RIGHT, got: {}", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<u64, anyhow::Error> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let suffix = &upper[digits.len()..];
+    let unit: u64 = match suffix.strip_suffix('B').unwrap_or(suffix) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(anyhow!("Expected a size like 500K or 10M, got: {}", s)),
+    };
+    match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(unit)) {
+        Some(n) if n > 0 => Ok(n),
+        _ => Err(anyhow!("Expected a size like 500K or 10M, got: {}", s)),
+    }
+}
+
+fn parse_join_kind(s: &str) -> Result<JoinKind, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_keep(s: &str) -> Result<Keep, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_date_format(s: &str) -> Result<String, anyhow::Error> {
+    // parsing alone accepts specifiers like %z that a plain date cannot fill
+    let parsed = StrftimeItems::new(s).all(|item| !matches!(item, Item::Error));
+    let sample = NaiveDate::from_ymd_opt(2000, 1, 1).and_then(|d| d.and_hms_opt(0, 0, 0));
+    let mut out = String::new();
+    match sample {
+        Some(dt) if parsed && write!(out, "{}", dt.format(s)).is_ok() => Ok(s.to_string()),
+        _ => Err(anyhow!("Invalid date format: {}", s)),
+    }
+}
+
+fn parse_ascii_char(s: &str) -> Result<u8, anyhow::Error> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(anyhow!("Expected a single ASCII character, got: {}", s)),
+    }
+}
+
 impl From<OutputFormat> for &'static str {
     fn from(format: OutputFormat) -> Self {
         match format {
             OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Table => "table",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Xml => "xml",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
         }
     }
 }
@@ -46,7 +833,16 @@ impl FromStr for OutputFormat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
             "yaml" => Ok(OutputFormat::Yaml),
+            "table" => Ok(OutputFormat::Table),
+            "toml" => Ok(OutputFormat::Toml),
+            "xml" => Ok(OutputFormat::Xml),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(OutputFormat::Arrow),
             _ => Err(anyhow!("Unknown output format: {}", s)),
         }
     }
@@ -56,4 +852,342 @@ impl fmt::Display for OutputFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", Into::<&'static str>::into(*self))
     }
-}
\ No newline at end of file
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            "yaml" => Ok(InputFormat::Yaml),
+            _ => Err(anyhow!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" | "uncompressed" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(anyhow!("Unknown compression: {}", s)),
+        }
+    }
+}
+
+impl FromStr for Mask {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rule, arg) = match s.split_once(':') {
+            Some((rule, arg)) => (rule, Some(arg)),
+            None => (s, None),
+        };
+        match (rule, arg) {
+            ("redact", None) => Ok(Mask::Redact("***".to_string())),
+            ("redact", Some(text)) => Ok(Mask::Redact(text.to_string())),
+            ("truncate", Some(n)) => Ok(Mask::Truncate(n.parse()?)),
+            ("fake", Some(kind)) => Ok(Mask::Fake(kind.parse()?)),
+            ("hash", None) => Ok(Mask::Hash),
+            _ => Err(anyhow!("Unknown mask: {}", s)),
+        }
+    }
+}
+
+impl FromStr for FakeKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(FakeKind::Name),
+            "email" => Ok(FakeKind::Email),
+            "phone" => Ok(FakeKind::Phone),
+            _ => Err(anyhow!("Unknown fake data kind: {}", s)),
+        }
+    }
+}
+
+impl FromStr for QuoteStyle {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(QuoteStyle::Always),
+            "necessary" => Ok(QuoteStyle::Necessary),
+            "non-numeric" => Ok(QuoteStyle::NonNumeric),
+            "never" => Ok(QuoteStyle::Never),
+            _ => Err(anyhow!("Unknown quote style: {}", s)),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lex" => Ok(SortOrder::Lexical),
+            "num" => Ok(SortOrder::Numeric),
+            "nat" => Ok(SortOrder::Natural),
+            _ => Err(anyhow!("Unknown sort order: {}", s)),
+        }
+    }
+}
+
+impl FromStr for OnError {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnError::Skip),
+            "reject" => Ok(OnError::Reject),
+            "fail" => Ok(OnError::Fail),
+            _ => Err(anyhow!("Expected skip, reject or fail, got: {}", s)),
+        }
+    }
+}
+
+impl FromStr for JoinKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "right" => Ok(JoinKind::Right),
+            "full" | "outer" => Ok(JoinKind::Full),
+            _ => Err(anyhow!("Unknown join type: {}", s)),
+        }
+    }
+}
+
+impl FromStr for Keep {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Keep::First),
+            "last" => Ok(Keep::Last),
+            _ => Err(anyhow!("Expected first or last, got: {}", s)),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ReportFormat::Human),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow!("Unknown report format: {}", s)),
+        }
+    }
+}
+
+impl From<AggFunc> for &'static str {
+    fn from(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+            AggFunc::Mean => "mean",
+            AggFunc::Median => "median",
+            AggFunc::Distinct => "distinct",
+        }
+    }
+}
+
+impl FromStr for AggFunc {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(AggFunc::Count),
+            "sum" => Ok(AggFunc::Sum),
+            "min" => Ok(AggFunc::Min),
+            "max" => Ok(AggFunc::Max),
+            "mean" | "avg" => Ok(AggFunc::Mean),
+            "median" => Ok(AggFunc::Median),
+            "distinct" => Ok(AggFunc::Distinct),
+            _ => Err(anyhow!("Unknown aggregate: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AggFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&'static str>::into(*self))
+    }
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+            ColumnType::String => "string",
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" => Ok(ColumnType::Int),
+            "float" => Ok(ColumnType::Float),
+            "bool" => Ok(ColumnType::Bool),
+            "date" => Ok(ColumnType::Date),
+            "string" => Ok(ColumnType::String),
+            _ => Err(anyhow!("Unknown column type: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&'static str>::into(*self))
+    }
+}
+
+/// `rcli csv ARGS`, for tests. Without `-i` or a subcommand the input is
+/// stdin.
+#[cfg(test)]
+pub fn test_opts(args: &[&str]) -> CsvOpts {
+    let input: &[&str] = match args.first() {
+        Some(name) if <CsvSubCommand as clap::Subcommand>::has_subcommand(name) => &[],
+        _ if args.contains(&"-i") => &[],
+        _ => &["-i", "-"],
+    };
+    CsvOpts::try_parse_from(["csv"].iter().chain(input).chain(args))
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ascii_char() {
+        assert_eq!(parse_ascii_char(";").unwrap(), b';');
+        assert_eq!(parse_ascii_char("\\t").unwrap(), b'\t');
+        assert!(parse_ascii_char("ab").is_err());
+        assert!(parse_ascii_char("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_column_type() {
+        assert_eq!(
+            parse_column_type("Accession No=float").unwrap(),
+            ("Accession No".to_string(), ColumnType::Float)
+        );
+        assert!(parse_column_type("id").is_err());
+        assert!(parse_column_type("id=time").is_err());
+    }
+
+    #[test]
+    fn test_parse_date_format() {
+        assert!(parse_date_format("%d.%m.%Y %H:%M").is_ok());
+        assert!(parse_date_format("%Q").is_err());
+        assert!(parse_date_format("%Y %z").is_err());
+        assert!(parse_date_format("%Z").is_err());
+    }
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_eq!(
+            parse_sort_key("Date:desc:nat").unwrap(),
+            SortKey {
+                column: "Date".to_string(),
+                descending: true,
+                order: SortOrder::Natural,
+            }
+        );
+        let key = parse_sort_key("time:12:num").unwrap();
+        assert_eq!(
+            (key.column.as_str(), key.order),
+            ("time:12", SortOrder::Numeric)
+        );
+        assert!(parse_sort_key(":desc").is_err());
+    }
+
+    #[test]
+    fn test_parse_mask_rule() {
+        let rule = |s| parse_mask_rule(s).map(|r| (r.column, r.mask)).ok();
+        assert_eq!(rule("Email=hash"), Some(("Email".to_string(), Mask::Hash)));
+        assert_eq!(
+            rule("a=b=truncate:2"),
+            Some(("a=b".to_string(), Mask::Truncate(2)))
+        );
+        assert_eq!(
+            rule("Notes=redact:n=a"),
+            Some(("Notes".to_string(), Mask::Redact("n=a".to_string())))
+        );
+        assert_eq!(
+            rule("Creator=fake:name"),
+            Some(("Creator".to_string(), Mask::Fake(FakeKind::Name)))
+        );
+        assert_eq!(rule("x=truncate"), None);
+        assert_eq!(rule("=hash"), None);
+    }
+
+    #[test]
+    fn test_parse_aggregate() {
+        assert_eq!(
+            parse_aggregate("median:Unit Price").unwrap(),
+            Aggregate {
+                func: AggFunc::Median,
+                column: Some("Unit Price".to_string()),
+            }
+        );
+        assert_eq!(parse_aggregate("count").unwrap().column, None);
+        assert!(parse_aggregate("sum").is_err());
+        assert!(parse_aggregate("total:Price").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10M").unwrap(), 10 << 20);
+        assert_eq!(parse_size("2kb").unwrap(), 2048);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10BB").is_err());
+        assert!(parse_size("10GBB").is_err());
+        assert!(parse_size("20000000000G").is_err());
+    }
+
+    #[test]
+    fn test_options_belong_to_their_subcommand() {
+        const FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let parse = |args: &[&str]| CsvOpts::try_parse_from(["csv"].iter().chain(args));
+        let opts = parse(&[
+            "agg",
+            "-i",
+            FILE,
+            "--where",
+            "a != \"\"",
+            "--head",
+            "2",
+            "-d",
+            ";",
+        ])
+        .unwrap();
+        let Some(CsvSubCommand::Agg(agg)) = &opts.cmd else {
+            unreachable!()
+        };
+        assert_eq!(
+            (agg.records.filter.as_deref(), agg.limit.head),
+            (Some("a != \"\""), Some(2))
+        );
+        assert_eq!(opts.dialect.delimiter, b';');
+        assert!(parse(&["diff", FILE, FILE, "--key", "id", "--dedup"]).is_err());
+        assert!(parse(&["validate", "-i", FILE, "--schema", FILE, "--mask", "a=hash"]).is_err());
+        assert!(parse(&["sql", "SELECT 1", "-i", FILE, "--select", "a"]).is_err());
+        assert!(parse(&["--where", "a", "agg", "-i", FILE]).is_err());
+        assert!(parse(&[]).is_err() && parse(&["agg", "--by", "a"]).is_err());
+        assert!(parse(&["-i", "-"]).is_ok() && parse(&["diff", FILE, FILE, "--key", "id"]).is_ok());
+    }
+}
@@ -1,15 +1,31 @@
-pub use self::{base64::Base64Format, csv::OutputFormat, text::TextSignFormat};
+#[cfg(test)]
+pub use self::csv::test_opts;
+pub use self::{
+    base64::Base64Format,
+    csv::{
+        AggFunc, Aggregate, ColumnType, Compression, FakeKind, InputFormat, JoinKind, Keep, Mask,
+        MaskRule, OnError, OutputFormat, QuoteStyle, ReportFormat, SortKey, SortOrder, WriterOpts,
+    },
+    text::TextSignFormat,
+};
 pub use self::{
-    base64::Base64SubCommand, csv::CsvOpts, genpass::GenPassOpts, text::TextSubCommand,
-    http::{HttpServeOpts, HttpSubCommand},
+    base64::Base64SubCommand,
+    csv::{
+        AggOpts, ConversionOpts, CsvDialect, CsvOpts, CsvSubCommand, CsvWriterOpts, DateOpts,
+        DiffOpts, InputOpts, JoinOpts, MergeOpts, OutputOpts, RecordOpts, SplitOpts, SqlOpts,
+        StatsOpts, ValidateOpts,
+    },
+    genpass::GenPassOpts,
+    http::HttpSubCommand,
+    text::TextSubCommand,
 };
 use clap::Parser;
 use std::path::{Path, PathBuf};
 mod base64;
 mod csv;
 mod genpass;
-mod text;
 mod http;
+mod text;
 
 #[derive(Debug, Parser)]
 #[command(name="rcli",version,author,about,long_about = None)]
@@ -21,7 +37,7 @@ pub struct Opts {
 #[derive(Debug, Parser)]
 pub enum Subcommand {
     #[command(name = "csv", about = "Show CSV Or Convert CSV To Other Formats")]
-    Csv(CsvOpts),
+    Csv(Box<CsvOpts>),
     #[command(name = "genpass", about = "Generate a password for RCL")]
     GenPass(GenPassOpts),
     #[command(subcommand)]
@@ -40,6 +56,17 @@ pub fn verify_input_file(filename: &str) -> Result<String, &'static str> {
     }
 }
 
+/// Like `verify_input_file`, but also takes directories and glob patterns,
+/// which are expanded later.
+pub fn verify_input_pattern(pattern: &str) -> Result<String, &'static str> {
+    if pattern.contains(['*', '?', '[']) {
+        glob::Pattern::new(pattern).map_err(|_| "Invalid glob pattern")?;
+        Ok(pattern.into())
+    } else {
+        verify_input_file(pattern)
+    }
+}
+
 pub fn verify_path(path: &str) -> Result<PathBuf, &'static str> {
     let p = Path::new(path);
     if p.exists() && p.is_dir() {
@@ -58,4 +85,12 @@ mod tests {
         assert_eq!(verify_input_file("*"), Err("File does not exist"));
         assert_eq!(verify_input_file("Cargo.toml"), Ok("Cargo.toml".into()));
     }
+
+    #[test]
+    fn test_verify_input_pattern() {
+        assert_eq!(verify_input_pattern("src"), Ok("src".into()));
+        assert_eq!(verify_input_pattern("data/*.csv"), Ok("data/*.csv".into()));
+        assert_eq!(verify_input_pattern("[a"), Err("Invalid glob pattern"));
+        assert_eq!(verify_input_pattern("nope.csv"), Err("File does not exist"));
+    }
 }
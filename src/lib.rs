@@ -1,8 +1,10 @@
-mod process;
 mod cli;
+mod process;
 mod utils;
 
-pub use cli::{Opts, Subcommand, Base64SubCommand, TextSubCommand,TextSignFormat,HttpSubCommand};
-pub use utils::{get_reader};
+pub use cli::{
+    Base64SubCommand, CsvSubCommand, HttpSubCommand, Opts, OutputFormat, Subcommand,
+    TextSignFormat, TextSubCommand,
+};
 pub use process::*;
-
+pub use utils::{get_reader, get_writer};
@@ -1,9 +1,11 @@
-use RCLI::{
-    Base64SubCommand, HttpSubCommand, Opts, Subcommand, TextSignFormat, TextSubCommand,
-    process_csv, process_decode, process_encode, process_generate, process_genpass,
+use clap::Parser;
+use rcli::{
+    Base64SubCommand, CsvSubCommand, HttpSubCommand, Opts, Subcommand, TextSignFormat,
+    TextSubCommand, process_csv, process_csv_agg, process_csv_batch, process_csv_diff,
+    process_csv_join, process_csv_merge, process_csv_split, process_csv_sql, process_csv_stats,
+    process_csv_validate, process_decode, process_encode, process_generate, process_genpass,
     process_http_serve, process_text_sign, process_text_verify,
 };
-use clap::Parser;
 use std::fs;
 use zxcvbn::zxcvbn;
 
@@ -12,14 +14,47 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
     let opts = Opts::parse();
     match opts.cmd {
-        Subcommand::Csv(opts) => {
-            let output = if let Some(output) = opts.output {
-                output.clone()
-            } else {
-                format!("output.{}", opts.format)
-            };
-            process_csv(&opts.input, output, opts.format)?
-        }
+        Subcommand::Csv(opts) => match &opts.cmd {
+            Some(CsvSubCommand::Agg(agg)) => process_csv_agg(
+                &agg.input.single()?,
+                &agg.output.file(agg.writer.format)?,
+                &opts,
+                agg,
+            )?,
+            Some(CsvSubCommand::Stats(stats)) => {
+                let output = stats.output.as_deref().unwrap_or("-");
+                process_csv_stats(&stats.input.single()?, output, &opts, stats)?
+            }
+            Some(CsvSubCommand::Validate(validate)) => {
+                let output = validate.output.as_deref().unwrap_or("-");
+                process_csv_validate(&validate.input.single()?, output, &opts, validate)?
+            }
+            Some(CsvSubCommand::Join(join)) => process_csv_join(
+                &join.output.file(join.conversion.writer.format)?,
+                &opts,
+                join,
+            )?,
+            Some(CsvSubCommand::Diff(diff)) => {
+                process_csv_diff(diff.output.as_deref().unwrap_or("-"), &opts, diff)?
+            }
+            Some(CsvSubCommand::Split(split)) => {
+                process_csv_split(&split.input.single()?, &opts, split)?
+            }
+            Some(CsvSubCommand::Merge(merge)) => process_csv_merge(
+                &merge.output.file(merge.conversion.writer.format)?,
+                &opts,
+                merge,
+            )?,
+            Some(CsvSubCommand::Sql(sql)) => {
+                process_csv_sql(sql.output.as_deref().unwrap_or("-"), &opts, sql)?
+            }
+            None if opts.output.path.is_none() => process_csv_batch(&opts)?,
+            None => process_csv(
+                &opts.input.single()?,
+                &opts.output.file(opts.conversion.writer.format)?,
+                &opts,
+            )?,
+        },
         Subcommand::GenPass(opts) => {
             let generated_password = process_genpass(
                 opts.length,
@@ -81,8 +116,7 @@ async fn main() -> anyhow::Result<()> {
             }
         },
         Subcommand::Http(cmd) => match cmd {
-            HttpSubCommand::Serve(opts) =>
-                process_http_serve(opts.dir, opts.port).await?,
+            HttpSubCommand::Serve(opts) => process_http_serve(opts.dir, opts.port).await?,
         },
     }
     Ok(())
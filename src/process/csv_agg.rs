@@ -0,0 +1,305 @@
+use crate::cli::{AggFunc, AggOpts, Aggregate, CsvOpts};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_convert::{line_of, prepared_records};
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_sort::numeric_cmp;
+use crate::process::csv_source::Input;
+use crate::process::csv_types::{parse_float, parse_int};
+use crate::process::csv_writer::{open_output, record_writer};
+use anyhow::{Context, Result, anyhow};
+use csv::StringRecord;
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::BufWriter;
+
+/// `rcli csv agg`: one output record per group, holding the key columns and
+/// the aggregates, with groups in the order they first appear. `--head` and
+/// `--tail` pick from the groups.
+pub fn process_csv_agg(input: &str, output: &str, opts: &CsvOpts, agg: &AggOpts) -> Result<()> {
+    let input = Input::new(input, agg.records.keep_last())?;
+    let rejects = Rejects::new(opts);
+    let (headers, records) = prepared_records(|r| input.records(opts, r), &agg.records, &rejects)?;
+    let mut aggregation = Aggregation::new(&headers, agg)?;
+    for record in records {
+        aggregation.add(&record?)?;
+    }
+    let output =
+        open_output(output, &agg.writer).with_context(|| format!("creating {}", output))?;
+    let mut writer = record_writer(&agg.writer, BufWriter::new(output));
+    let groups: Vec<Value> = aggregation
+        .finish()
+        .take(agg.limit.head.unwrap_or(usize::MAX))
+        .collect();
+    let skip = agg.limit.tail.map_or(0, |n| groups.len().saturating_sub(n));
+    for value in &groups[skip..] {
+        writer.write_record(value)?;
+    }
+    writer.finish()?;
+    rejects.finish()
+}
+
+/// Running aggregates for every group seen so far.
+struct Aggregation {
+    keys: Vec<(String, usize)>,
+    columns: Vec<(String, AggFunc, Option<usize>)>,
+    index: HashMap<Vec<String>, usize>,
+    groups: Vec<(Vec<String>, Vec<State>)>,
+}
+
+impl Aggregation {
+    fn new(headers: &StringRecord, opts: &AggOpts) -> Result<Self> {
+        let keys = opts
+            .by
+            .iter()
+            .map(|c| {
+                let idx = column_index(headers, c).context("in --by")?;
+                Ok((headers[idx].to_string(), idx))
+            })
+            .collect::<Result<_>>()?;
+        let count = [Aggregate {
+            func: AggFunc::Count,
+            column: None,
+        }];
+        let aggregates = if opts.aggregates.is_empty() {
+            &count[..]
+        } else {
+            &opts.aggregates[..]
+        };
+        let columns = aggregates
+            .iter()
+            .map(|a| match &a.column {
+                Some(c) => {
+                    let idx = column_index(headers, c).context("in --aggregate")?;
+                    Ok((format!("{}_{}", a.func, &headers[idx]), a.func, Some(idx)))
+                }
+                None => Ok((a.func.to_string(), a.func, None)),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            keys,
+            columns,
+            index: HashMap::new(),
+            groups: Vec::new(),
+        })
+    }
+
+    fn add(&mut self, record: &StringRecord) -> Result<()> {
+        let key: Vec<String> = self
+            .keys
+            .iter()
+            .map(|&(_, i)| record.get(i).unwrap_or("").to_string())
+            .collect();
+        let group = match self.index.get(&key) {
+            Some(&group) => group,
+            None => {
+                let states = self
+                    .columns
+                    .iter()
+                    .map(|&(_, func, _)| State::new(func))
+                    .collect();
+                self.index.insert(key.clone(), self.groups.len());
+                self.groups.push((key, states));
+                self.groups.len() - 1
+            }
+        };
+        let states = &mut self.groups[group].1;
+        for ((name, _, column), state) in self.columns.iter().zip(states) {
+            let field = column.map(|i| record.get(i).unwrap_or(""));
+            state
+                .add(field)
+                .with_context(|| format!("{} at line {}", name, line_of(record)))?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> impl Iterator<Item = Value> {
+        self.groups.into_iter().map(move |(key, states)| {
+            let mut map = Map::new();
+            for ((name, _), value) in self.keys.iter().zip(key) {
+                map.insert(name.clone(), Value::String(value));
+            }
+            for ((name, _, _), state) in self.columns.iter().zip(states) {
+                map.insert(name.clone(), state.value());
+            }
+            Value::Object(map)
+        })
+    }
+}
+
+/// The running value of one aggregate. Empty cells are skipped by all but a
+/// plain `count`, which counts records.
+enum State {
+    Count(u64),
+    Sum(f64),
+    Min(Option<String>),
+    Max(Option<String>),
+    Mean(f64, u64),
+    Median(Vec<f64>),
+    Distinct(HashSet<String>),
+}
+
+impl State {
+    fn new(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => State::Count(0),
+            AggFunc::Sum => State::Sum(0.0),
+            AggFunc::Min => State::Min(None),
+            AggFunc::Max => State::Max(None),
+            AggFunc::Mean => State::Mean(0.0, 0),
+            AggFunc::Median => State::Median(Vec::new()),
+            AggFunc::Distinct => State::Distinct(HashSet::new()),
+        }
+    }
+
+    fn add(&mut self, field: Option<&str>) -> Result<()> {
+        let field = match field {
+            None => {
+                if let State::Count(n) = self {
+                    *n += 1;
+                }
+                return Ok(());
+            }
+            Some("") => return Ok(()),
+            Some(field) => field,
+        };
+        match self {
+            State::Count(n) => *n += 1,
+            State::Sum(sum) => *sum += number(field)?,
+            State::Min(min) => keep_if(min, field, Ordering::Less),
+            State::Max(max) => keep_if(max, field, Ordering::Greater),
+            State::Mean(sum, n) => {
+                *sum += number(field)?;
+                *n += 1;
+            }
+            State::Median(values) => values.push(number(field)?),
+            State::Distinct(seen) => {
+                if !seen.contains(field) {
+                    seen.insert(field.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value(self) -> Value {
+        match self {
+            State::Count(n) => Value::from(n),
+            State::Sum(sum) => float_value(sum),
+            State::Min(v) | State::Max(v) => v.map_or(Value::Null, |v| scalar_value(&v)),
+            State::Mean(_, 0) => Value::Null,
+            State::Mean(sum, n) => float_value(sum / n as f64),
+            State::Median(mut values) if !values.is_empty() => {
+                values.sort_by(f64::total_cmp);
+                let mid = values.len() / 2;
+                if values.len() % 2 == 1 {
+                    float_value(values[mid])
+                } else {
+                    float_value((values[mid - 1] + values[mid]) / 2.0)
+                }
+            }
+            State::Median(_) => Value::Null,
+            State::Distinct(seen) => Value::from(seen.len()),
+        }
+    }
+}
+
+/// Min and max compare numbers by value and everything else as text.
+fn keep_if(current: &mut Option<String>, field: &str, wanted: Ordering) {
+    if current
+        .as_deref()
+        .is_none_or(|c| numeric_cmp(field, c) == wanted)
+    {
+        *current = Some(field.to_string());
+    }
+}
+
+fn number(field: &str) -> Result<f64> {
+    field
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Cannot parse {:?} as a number", field))
+}
+
+/// Whole results are printed as integers, so a sum of prices stays `12`
+/// rather than `12.0`.
+fn float_value(x: f64) -> Value {
+    if x.fract() == 0.0 && x.abs() < 9_007_199_254_740_992.0 {
+        Value::from(x as i64)
+    } else {
+        Number::from_f64(x).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn scalar_value(s: &str) -> Value {
+    if let Some(i) = parse_int(s) {
+        Value::from(i)
+    } else if let Some(f) = parse_float(s).and_then(Number::from_f64) {
+        Value::Number(f)
+    } else {
+        Value::String(s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvSubCommand;
+    use crate::cli::test_opts;
+    use serde_json::json;
+
+    fn aggregate(args: &[&str], rows: &[&[&str]]) -> Result<Vec<Value>> {
+        let Some(CsvSubCommand::Agg(opts)) = test_opts(&[&["agg", "-i", "-"], args].concat()).cmd
+        else {
+            unreachable!()
+        };
+        let headers = StringRecord::from(vec!["Creator", "Price", "Tag"]);
+        let mut aggregation = Aggregation::new(&headers, &opts)?;
+        for row in rows {
+            aggregation.add(&StringRecord::from(row.to_vec()))?;
+        }
+        Ok(aggregation.finish().collect())
+    }
+
+    const ROWS: &[&[&str]] = &[
+        &["Doe", "2", "a"],
+        &["Roe", "1.5", "b"],
+        &["Doe", "10", "a"],
+        &["Doe", "", "c"],
+    ];
+
+    #[test]
+    fn test_group_aggregates() -> Result<()> {
+        let args = [
+            "--by",
+            "Creator",
+            "-a",
+            "count,sum:Price,min:Price,max:Price,mean:Price,median:Price,distinct:Tag",
+        ];
+        let groups = aggregate(&args, ROWS)?;
+        assert_eq!(
+            groups[0],
+            json!({"Creator": "Doe", "count": 3, "sum_Price": 12, "min_Price": 2, "max_Price": 10,
+                   "mean_Price": 6, "median_Price": 6, "distinct_Tag": 2})
+        );
+        assert_eq!(groups[1]["sum_Price"], json!(1.5));
+        assert_eq!(groups.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_without_groups() -> Result<()> {
+        assert_eq!(aggregate(&[], ROWS)?, vec![json!({"count": 4})]);
+        assert_eq!(
+            aggregate(&["-a", "count:Price"], ROWS)?,
+            vec![json!({"count_Price": 3})]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_numeric_sum_fails() {
+        assert!(aggregate(&["-a", "sum:Tag"], ROWS).is_err());
+        assert!(aggregate(&["--by", "Nope"], ROWS).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::cli::{CsvOpts, InputOpts, OutputFormat, OutputOpts};
+use crate::process::csv_convert::{convert_input, process_csv};
+use crate::process::csv_reject::Rejects;
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files picked up when a directory is given as input.
+const INPUT_EXTENSIONS: [&str; 7] = ["csv", "tsv", "json", "jsonl", "ndjson", "yaml", "yml"];
+
+/// `rcli csv` without `--output`: convert every input to a file named after
+/// it, next to it or in `--out-dir`. All names are checked before anything is
+/// written, and existing files are only replaced with `--force`. Malformed
+/// records of all inputs go to the one reject file.
+pub fn process_csv_batch(opts: &CsvOpts) -> Result<()> {
+    let format = opts.conversion.writer.format;
+    let inputs = expand_inputs(&opts.input.paths)?;
+    if matches!(format, OutputFormat::Table) {
+        for input in &inputs {
+            process_csv(input, "-", opts)?;
+        }
+        return Ok(());
+    }
+    let mut seen = HashSet::new();
+    let mut outputs = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let output = output_path(input, opts.out_dir.as_deref(), format);
+        if !seen.insert(output.clone()) {
+            bail!(
+                "{} would overwrite the output of another input: {}",
+                input,
+                output.display()
+            );
+        }
+        if output.exists() {
+            if input != "-" && fs::canonicalize(input)? == fs::canonicalize(&output)? {
+                bail!(
+                    "Converting {} would overwrite it; pass --output or --out-dir",
+                    input
+                );
+            }
+            if !opts.output.force {
+                bail!(
+                    "{} already exists; pass --force to overwrite it",
+                    output.display()
+                );
+            }
+        }
+        outputs.push(output.to_string_lossy().into_owned());
+    }
+    let rejects = Rejects::new(opts);
+    for (input, output) in inputs.iter().zip(&outputs) {
+        convert_input(input, output, opts, &rejects)
+            .with_context(|| format!("converting {}", input))?;
+        if inputs.len() > 1 {
+            eprintln!("{} -> {}", input, output);
+        }
+    }
+    rejects.finish()
+}
+
+impl InputOpts {
+    /// The input of the commands that read a single file.
+    pub fn single(&self) -> Result<String> {
+        let mut inputs = expand_inputs(&self.paths)?;
+        if inputs.len() != 1 {
+            bail!("Expected a single input, found {}", inputs.len());
+        }
+        Ok(inputs.remove(0))
+    }
+}
+
+impl OutputOpts {
+    /// `--output`, or by default stdout for tables and otherwise
+    /// `output.{format}`, which like the other derived names is only
+    /// replaced with `--force`.
+    pub fn file(&self, format: OutputFormat) -> Result<String> {
+        if let Some(output) = &self.path {
+            return Ok(output.clone());
+        }
+        if matches!(format, OutputFormat::Table) {
+            return Ok("-".to_string());
+        }
+        let output = format!("output.{}", format);
+        if Path::new(&output).exists() && !self.force {
+            bail!(
+                "{} already exists; pass --output, or --force to overwrite it",
+                output
+            );
+        }
+        Ok(output)
+    }
+}
+
+/// Expand directories to the input files they contain and glob patterns to
+/// the files they match, in name order.
+pub fn expand_inputs(patterns: &[String]) -> Result<Vec<String>> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        let start = inputs.len();
+        if Path::new(pattern).is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(pattern).with_context(|| format!("reading {}", pattern))? {
+                let path = entry?.path();
+                let ext = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(str::to_ascii_lowercase);
+                if path.is_file() && ext.is_some_and(|e| INPUT_EXTENSIONS.contains(&e.as_str())) {
+                    files.push(path);
+                }
+            }
+            files.sort();
+            inputs.extend(files.iter().map(|p| p.to_string_lossy().into_owned()));
+        } else if pattern.contains(['*', '?', '[']) {
+            for path in glob::glob(pattern)? {
+                let path = path?;
+                if path.is_file() {
+                    inputs.push(path.to_string_lossy().into_owned());
+                }
+            }
+        } else {
+            inputs.push(pattern.clone());
+        }
+        if inputs.len() == start {
+            bail!("No input files found in {}", pattern);
+        }
+    }
+    if inputs.iter().filter(|i| *i == "-").count() > 1 {
+        bail!("Stdin can only be read once");
+    }
+    Ok(inputs)
+}
+
+/// `data/items.csv` becomes `items.json` in `out_dir` or in `data`; stdin
+/// becomes `output.json`.
+fn output_path(input: &str, out_dir: Option<&Path>, format: OutputFormat) -> PathBuf {
+    let ext: &'static str = format.into();
+    let path = Path::new(input);
+    let (stem, dir) = if input == "-" {
+        ("output".as_ref(), Path::new(""))
+    } else {
+        (
+            path.file_stem().unwrap_or_default(),
+            path.parent().unwrap_or(Path::new("")),
+        )
+    };
+    let mut name = stem.to_os_string();
+    name.push(".");
+    name.push(ext);
+    out_dir.unwrap_or(dir).join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+
+    #[test]
+    fn test_output_path() {
+        let json = OutputFormat::Json;
+        assert_eq!(
+            output_path("data/items.v2.csv", None, json),
+            Path::new("data/items.v2.json")
+        );
+        assert_eq!(
+            output_path("items.csv", None, json),
+            Path::new("items.json")
+        );
+        assert_eq!(
+            output_path("data/items", Some(Path::new("out")), OutputFormat::Markdown),
+            Path::new("out/items.md")
+        );
+        assert_eq!(output_path("-", None, json), Path::new("output.json"));
+    }
+
+    #[test]
+    fn test_expand_inputs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["b.csv", "a.JSON", "notes.txt"] {
+            fs::write(dir.path().join(name), "x\n1\n")?;
+        }
+        let root = dir.path().to_string_lossy().into_owned();
+        let names = |inputs: Vec<String>| -> Vec<String> {
+            inputs
+                .iter()
+                .map(|i| {
+                    Path::new(i)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+        assert_eq!(
+            names(expand_inputs(std::slice::from_ref(&root))?),
+            ["a.JSON", "b.csv"]
+        );
+        assert_eq!(
+            names(expand_inputs(&[format!("{}/*.csv", root), "-".into()])?),
+            ["b.csv", "-"]
+        );
+        assert!(expand_inputs(&[format!("{}/*.xml", root)]).is_err());
+        assert!(expand_inputs(&["-".into(), "-".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_needs_force_to_overwrite() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("items.csv");
+        fs::write(&input, "id\n1\n")?;
+        fs::write(dir.path().join("items.json"), "old")?;
+        let input = input.to_string_lossy().into_owned();
+
+        let opts = test_opts(&["-i", &input]);
+        assert!(process_csv_batch(&opts).is_err());
+        assert_eq!(fs::read_to_string(dir.path().join("items.json"))?, "old");
+
+        let opts = test_opts(&["-i", &input, "--force"]);
+        process_csv_batch(&opts)?;
+        assert!(fs::read_to_string(dir.path().join("items.json"))?.contains("\"id\""));
+
+        let opts = test_opts(&["-i", &input, "--format", "csv", "--force"]);
+        assert!(process_csv_batch(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_shares_reject_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let (x, y, rejected) = (path("x.csv"), path("y.csv"), path("rejected.csv"));
+        for input in [&x, &y] {
+            fs::write(input, "id\n1\nx,2\n")?;
+        }
+        let out_dir = dir.path().to_string_lossy();
+        let args = [
+            "--out-dir",
+            &out_dir,
+            "-i",
+            &x,
+            "-i",
+            &y,
+            "--on-error",
+            "reject",
+            "--reject-file",
+            &rejected,
+        ];
+        process_csv_batch(&test_opts(&args))?;
+        let rejected = fs::read_to_string(rejected)?;
+        let sources: Vec<_> = rejected
+            .lines()
+            .map(|l| l.split(',').next().unwrap())
+            .collect();
+        assert_eq!(sources.len(), 2);
+        assert!(sources[0].ends_with("x.csv") && sources[1].ends_with("y.csv"));
+        Ok(())
+    }
+}
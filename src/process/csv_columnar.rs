@@ -0,0 +1,341 @@
+use crate::cli::{ColumnType, Compression, OutputFormat};
+use crate::process::csv_writer::{FieldType, RecordWriter};
+use anyhow::{Result, anyhow, bail};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::CompressionType;
+use arrow::ipc::writer::{FileWriter, IpcWriteOptions};
+use arrow::json::ReaderBuilder;
+use arrow::json::reader::infer_json_schema_from_iterator;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression as ParquetCompression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Records buffered before they are written out as one record batch.
+const BATCH_ROWS: usize = 8192;
+
+/// Writes Parquet or Arrow IPC files. The schema comes from the columns the
+/// records are announced with, typed by inference over the whole input;
+/// only nested fields, and records written without columns, are typed from
+/// the values of the first batch, with fields that are empty there as
+/// strings.
+pub struct ColumnarWriter<W: Write + Send> {
+    format: OutputFormat,
+    compression: Option<Compression>,
+    columns: Option<Vec<(String, FieldType)>>,
+    rows: Vec<Value>,
+    sink: Option<Sink<W>>,
+    writer: Option<W>,
+}
+
+enum Sink<W: Write + Send> {
+    Parquet(ArrowWriter<W>, SchemaRef),
+    Arrow(FileWriter<W>, SchemaRef),
+}
+
+impl<W: Write + Send> Sink<W> {
+    fn schema(&self) -> SchemaRef {
+        match self {
+            Sink::Parquet(_, schema) | Sink::Arrow(_, schema) => schema.clone(),
+        }
+    }
+}
+
+impl<W: Write + Send> ColumnarWriter<W> {
+    pub fn new(writer: W, format: OutputFormat, compression: Option<Compression>) -> Self {
+        Self {
+            format,
+            compression,
+            columns: None,
+            rows: Vec::with_capacity(BATCH_ROWS),
+            sink: None,
+            writer: Some(writer),
+        }
+    }
+
+    fn open(&mut self, schema: SchemaRef) -> Result<Sink<W>> {
+        let writer = self.writer.take().expect("the output is only opened once");
+        let sink = match self.format {
+            OutputFormat::Parquet => {
+                let compression = match self.compression.unwrap_or(Compression::Snappy) {
+                    Compression::None => ParquetCompression::UNCOMPRESSED,
+                    Compression::Snappy => ParquetCompression::SNAPPY,
+                    Compression::Gzip => ParquetCompression::GZIP(GzipLevel::default()),
+                    Compression::Zstd => ParquetCompression::ZSTD(ZstdLevel::default()),
+                    Compression::Lz4 => ParquetCompression::LZ4_RAW,
+                };
+                let props = WriterProperties::builder()
+                    .set_compression(compression)
+                    .build();
+                Sink::Parquet(
+                    ArrowWriter::try_new(writer, schema.clone(), Some(props))?,
+                    schema,
+                )
+            }
+            _ => {
+                let compression = match self.compression.unwrap_or(Compression::None) {
+                    Compression::None => None,
+                    Compression::Zstd => Some(CompressionType::ZSTD),
+                    Compression::Lz4 => Some(CompressionType::LZ4_FRAME),
+                    other => bail!(
+                        "Arrow output only supports zstd or lz4 compression, not {:?}",
+                        other
+                    ),
+                };
+                let options = IpcWriteOptions::default().try_with_compression(compression)?;
+                Sink::Arrow(
+                    FileWriter::try_new_with_options(writer, &schema, options)?,
+                    schema,
+                )
+            }
+        };
+        Ok(sink)
+    }
+
+    fn flush_rows(&mut self) -> Result<()> {
+        if self.sink.is_none() {
+            let schema = match &self.columns {
+                Some(columns) => column_schema(columns, &self.rows)?,
+                None => batch_schema(&self.rows)?,
+            };
+            self.sink = Some(self.open(schema)?);
+        }
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let sink = self.sink.as_mut().expect("the output was just opened");
+        let batch = record_batch(sink.schema(), &self.rows)?;
+        self.rows.clear();
+        match sink {
+            Sink::Parquet(writer, _) => writer.write(&batch)?,
+            Sink::Arrow(writer, _) => writer.write(&batch)?,
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> RecordWriter for ColumnarWriter<W> {
+    fn set_columns(&mut self, columns: &[(String, FieldType)]) {
+        self.columns = Some(columns.to_vec());
+    }
+
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if !record.is_object() {
+            bail!("{} output needs records that are objects", self.format);
+        }
+        self.rows.push(record.clone());
+        if self.rows.len() == BATCH_ROWS {
+            self.flush_rows()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush_rows()?;
+        let mut writer = match self.sink.take() {
+            Some(Sink::Parquet(writer, _)) => writer.into_inner()?,
+            Some(Sink::Arrow(mut writer, _)) => {
+                writer.finish()?;
+                writer.into_inner()?
+            }
+            None => return Ok(()),
+        };
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The schema for records with known columns. Nested fields are typed from
+/// the values in `rows`, like a whole batch is by `batch_schema`.
+fn column_schema(columns: &[(String, FieldType)], rows: &[Value]) -> Result<SchemaRef> {
+    let inferred = if columns.iter().any(|(_, ty)| *ty == FieldType::Nested) {
+        Some(batch_schema(rows)?)
+    } else {
+        None
+    };
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|(name, ty)| {
+            let data_type = match ty {
+                FieldType::Scalar(Some(ColumnType::Int)) => DataType::Int64,
+                FieldType::Scalar(Some(ColumnType::Float)) => DataType::Float64,
+                FieldType::Scalar(Some(ColumnType::Bool)) => DataType::Boolean,
+                // dates are ISO 8601, with a time only when they have one
+                FieldType::Scalar(Some(ColumnType::Date)) => {
+                    DataType::Timestamp(TimeUnit::Second, None)
+                }
+                FieldType::Scalar(Some(ColumnType::String) | None) => DataType::Utf8,
+                FieldType::Nested => inferred
+                    .as_ref()
+                    .and_then(|schema| schema.field_with_name(name).ok())
+                    .map_or(DataType::Utf8, |f| f.data_type().clone()),
+            };
+            Field::new(name, data_type, true)
+        })
+        .collect();
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// The schema of a batch of records, with every column nullable and the
+/// columns that only hold nulls typed as strings.
+fn batch_schema(rows: &[Value]) -> Result<SchemaRef> {
+    let inferred = infer_json_schema_from_iterator(rows.iter().map(Ok))?;
+    let fields: Vec<Field> = inferred
+        .fields()
+        .iter()
+        .map(|f| match f.data_type() {
+            DataType::Null => Field::new(f.name(), DataType::Utf8, true),
+            _ => f.as_ref().clone().with_nullable(true),
+        })
+        .collect();
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Decode the records against `schema`. Numbers and booleans found in string
+/// columns are written as text.
+fn record_batch(schema: SchemaRef, rows: &[Value]) -> Result<RecordBatch> {
+    let mut decoder = ReaderBuilder::new(schema)
+        .with_batch_size(rows.len())
+        .with_coerce_primitive(true)
+        .build_decoder()?;
+    decoder.serialize(rows)?;
+    decoder
+        .flush()?
+        .ok_or_else(|| anyhow!("no records to write"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::Int64Type;
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn write_all(
+        format: OutputFormat,
+        compression: Option<Compression>,
+        records: &[Value],
+    ) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut writer = ColumnarWriter::new(&mut buf, format, compression);
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        Ok(buf)
+    }
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({"id": 1, "name": "a", "score": 1.5, "note": null}),
+            json!({"id": 2, "name": null, "score": 2.0, "note": null}),
+        ]
+    }
+
+    #[test]
+    fn test_parquet_keeps_types() -> Result<()> {
+        for compression in [None, Some(Compression::Zstd), Some(Compression::Gzip)] {
+            let buf = write_all(OutputFormat::Parquet, compression, &records())?;
+            let mut file = tempfile::tempfile()?;
+            file.write_all(&buf)?;
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+            let batches = reader.collect::<Result<Vec<_>, _>>()?;
+            let batch = &batches[0];
+            assert_eq!(batch.num_rows(), 2);
+            let types: Vec<_> = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.data_type().clone())
+                .collect();
+            assert_eq!(
+                types,
+                [
+                    DataType::Int64,
+                    DataType::Utf8,
+                    DataType::Float64,
+                    DataType::Utf8
+                ]
+            );
+            assert_eq!(batch.column(0).as_primitive::<Int64Type>().value(1), 2);
+            assert!(batch.column(1).is_null(1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_from_columns() -> Result<()> {
+        let columns = [
+            ("id".to_string(), FieldType::Scalar(Some(ColumnType::Int))),
+            (
+                "when".to_string(),
+                FieldType::Scalar(Some(ColumnType::Date)),
+            ),
+            ("tags".to_string(), FieldType::Nested),
+        ];
+        let read = |rows: &[Value]| -> Result<(Schema, usize)> {
+            let mut buf = Vec::new();
+            let mut writer = ColumnarWriter::new(&mut buf, OutputFormat::Arrow, None);
+            writer.set_columns(&columns);
+            for row in rows {
+                writer.write_record(row)?;
+            }
+            writer.finish()?;
+            drop(writer);
+            let reader = FileReader::try_new(Cursor::new(buf), None)?;
+            let schema = reader.schema().as_ref().clone();
+            let rows: usize = reader
+                .map(|b| b.map(|b| b.num_rows()))
+                .sum::<Result<_, _>>()?;
+            Ok((schema, rows))
+        };
+        let types = |schema: &Schema| -> Vec<DataType> {
+            schema
+                .fields()
+                .iter()
+                .map(|f| f.data_type().clone())
+                .collect()
+        };
+
+        // a header-only input keeps its columns
+        let (schema, rows) = read(&[])?;
+        assert_eq!(rows, 0);
+        assert_eq!(
+            types(&schema),
+            [
+                DataType::Int64,
+                DataType::Timestamp(TimeUnit::Second, None),
+                DataType::Utf8
+            ]
+        );
+
+        // values after a first batch of nulls keep the column's type
+        let mut records = vec![json!({"id": null, "when": null, "tags": ["a"]}); BATCH_ROWS];
+        records.push(json!({"id": 7, "when": "2016-06-16T08:30:00", "tags": ["b"]}));
+        records.push(json!({"id": 8, "when": "2016-06-17", "tags": []}));
+        let (schema, rows) = read(&records)?;
+        assert_eq!(rows, BATCH_ROWS + 2);
+        assert_eq!(types(&schema)[0], DataType::Int64);
+        assert!(matches!(types(&schema)[2], DataType::List(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrow_ipc() -> Result<()> {
+        let buf = write_all(OutputFormat::Arrow, Some(Compression::Lz4), &records())?;
+        let reader = FileReader::try_new(Cursor::new(buf), None)?;
+        let rows: usize = reader
+            .map(|b| b.map(|b| b.num_rows()))
+            .sum::<Result<_, _>>()?;
+        assert_eq!(rows, 2);
+        assert!(write_all(OutputFormat::Arrow, Some(Compression::Snappy), &records()).is_err());
+        Ok(())
+    }
+}
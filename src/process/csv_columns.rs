@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow, bail};
+use csv::StringRecord;
+
+/// Find a column by name, or by its 1-based position when no header has that
+/// name.
+pub fn column_index(headers: &StringRecord, column: &str) -> Result<usize> {
+    if let Some(idx) = headers.iter().position(|h| h == column) {
+        return Ok(idx);
+    }
+    match column.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= headers.len() => Ok(n - 1),
+        _ => Err(anyhow!("Unknown column: {}", column)),
+    }
+}
+
+/// Column selection, ordering and renaming applied to every record.
+#[derive(Debug)]
+pub struct Projection {
+    indices: Vec<usize>,
+    headers: StringRecord,
+}
+
+impl Projection {
+    pub fn new(
+        headers: &StringRecord,
+        select: &[String],
+        exclude: &[String],
+        rename: &[(String, String)],
+    ) -> Result<Self> {
+        let mut indices = if select.is_empty() {
+            (0..headers.len()).collect()
+        } else {
+            select
+                .iter()
+                .map(|c| column_index(headers, c))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let excluded = exclude
+            .iter()
+            .map(|c| column_index(headers, c))
+            .collect::<Result<Vec<_>>>()?;
+        indices.retain(|i| !excluded.contains(i));
+
+        let mut names: Vec<String> = indices.iter().map(|&i| headers[i].to_string()).collect();
+        for (old, new) in rename {
+            let idx = column_index(headers, old)?;
+            let Some(pos) = indices.iter().position(|&i| i == idx) else {
+                bail!("Cannot rename column {} that is not selected", old);
+            };
+            names[pos] = new.clone();
+        }
+        Ok(Self {
+            indices,
+            headers: StringRecord::from(names),
+        })
+    }
+
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    pub fn apply(&self, record: &StringRecord) -> StringRecord {
+        let mut projected: StringRecord = self
+            .indices
+            .iter()
+            .map(|&i| record.get(i).unwrap_or(""))
+            .collect();
+        projected.set_position(record.position().cloned());
+        projected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> StringRecord {
+        StringRecord::from(vec!["Title", "Identifier", "Date", "Creator"])
+    }
+
+    #[test]
+    fn test_column_index() -> Result<()> {
+        assert_eq!(column_index(&headers(), "Date")?, 2);
+        assert_eq!(column_index(&headers(), "1")?, 0);
+        assert!(column_index(&headers(), "5").is_err());
+        assert!(column_index(&headers(), "0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_reorders_and_renames() -> Result<()> {
+        let rename = vec![("Date".to_string(), "when".to_string())];
+        let projection = Projection::new(&headers(), &["Date".into(), "1".into()], &[], &rename)?;
+        assert_eq!(
+            projection.headers(),
+            &StringRecord::from(vec!["when", "Title"])
+        );
+        let record = StringRecord::from(vec!["t", "id", "d", "c"]);
+        assert_eq!(projection.apply(&record), vec!["d", "t"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude() -> Result<()> {
+        let projection = Projection::new(&headers(), &[], &["Identifier".into(), "4".into()], &[])?;
+        assert_eq!(
+            projection.headers(),
+            &StringRecord::from(vec!["Title", "Date"])
+        );
+        assert!(
+            Projection::new(
+                &headers(),
+                &[],
+                &["Title".into()],
+                &[("Title".into(), "x".into())]
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+}
@@ -1,22 +1,415 @@
-use anyhow::Result;
-use csv::Reader;
-use serde_json::Value;
-use std::fs;
-use crate::cli::OutputFormat;
-
-pub fn process_csv(input: &str, output: String, format: OutputFormat) -> Result<()> {
-    let mut rdr = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let headers = rdr.headers()?.clone();
-    for result in rdr.records() {
-        let record = result?;
-        let json_value = headers.iter().zip(record.iter()).collect::<Value>();
-        ret.push(json_value);
-    }
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+use crate::cli::{ColumnType, ConversionOpts, CsvDialect, CsvOpts, Keep, RecordOpts};
+use crate::process::csv_columns::{Projection, column_index};
+use crate::process::csv_dates::DateParser;
+use crate::process::csv_filter::Filter;
+use crate::process::csv_mask::Masker;
+use crate::process::csv_nest::{PathSegment, header_path, nest_record};
+use crate::process::csv_reject::{MalformedRecord, Rejects};
+use crate::process::csv_sort::{Dedup, SortKeys, sort_records};
+use crate::process::csv_source::{Input, Records};
+use crate::process::csv_types::{TypeInference, column_types, typed_value};
+use crate::process::csv_writer::{FieldType, open_output, record_writer};
+use anyhow::{Context, Result};
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::io::{BufWriter, Read};
+
+/// Turns raw records into JSON values: typing, array splitting, date
+/// normalization and nesting, resolved once against the input's headers.
+pub struct RecordConverter {
+    headers: StringRecord,
+    types: Vec<Option<ColumnType>>,
+    separators: Vec<Option<String>>,
+    paths: Option<Vec<Vec<PathSegment>>>,
+    dates: DateParser,
+}
+
+/// Convert `input` record by record, so memory use does not grow with the
+/// number of rows. Type inference and `--keep last` read the input once more
+/// up front; sorting spills to temporary files past `--sort-buffer` records.
+/// Either side may be `-` for stdin/stdout.
+pub fn process_csv(input: &str, output: &str, opts: &CsvOpts) -> Result<()> {
+    let rejects = Rejects::new(opts);
+    convert_input(input, output, opts, &rejects)?;
+    rejects.finish()
+}
+
+/// Convert one input, with its malformed records handed to `rejects`, which
+/// several inputs may share.
+pub fn convert_input(input: &str, output: &str, opts: &CsvOpts, rejects: &Rejects) -> Result<()> {
+    let conversion = &opts.conversion;
+    let source = Input::new(
+        input,
+        conversion.infers_types() || conversion.records.keep_last(),
+    )?;
+    convert_records(
+        |r| source.records(opts, r),
+        output,
+        conversion,
+        &rejects.for_source(input),
+    )
+}
+
+/// Prepare, convert and write the records from `open`, which is called once
+/// for every pass over them.
+pub fn convert_records<F>(
+    open: F,
+    output: &str,
+    opts: &ConversionOpts,
+    rejects: &Rejects,
+) -> Result<()>
+where
+    F: Fn(&Rejects) -> Result<(StringRecord, Records)>,
+{
+    let inferred = if opts.infers_types() {
+        let (headers, records) = prepared_records(&open, &opts.records, &rejects.silent())?;
+        let separators = array_separators(&headers, &opts.convert.arrays)?;
+        Some(infer_types(&separators, opts.date_parser(), records)?)
+    } else {
+        None
+    };
+    let (headers, records) = prepared_records(&open, &opts.records, rejects)?;
+    let converter = RecordConverter::new(headers, inferred, opts)?;
+    let output =
+        open_output(output, &opts.writer).with_context(|| format!("creating {}", output))?;
+    let mut writer = record_writer(&opts.writer, BufWriter::new(output));
+    writer.set_columns(&converter.columns());
+    let limit = opts.limit.head.unwrap_or(usize::MAX);
+    let mut tail = VecDeque::with_capacity(opts.limit.tail.unwrap_or(0));
+    for record in records.take(limit) {
+        let record = record?;
+        let value = match converter.convert(&record) {
+            Ok(value) => value,
+            Err(e) => {
+                let reason = format!("{:#}", e);
+                rejects.handle(MalformedRecord::new(&record, line_of(&record), reason).into())?;
+                continue;
+            }
+        };
+        match opts.limit.tail {
+            Some(n) => {
+                if tail.len() == n {
+                    tail.pop_front();
+                }
+                if n > 0 {
+                    tail.push_back(value);
+                }
+            }
+            None => writer.write_record(&value)?,
+        }
+    }
+    for value in &tail {
+        writer.write_record(value)?;
+    }
+    writer.finish()
+}
+
+/// Apply the steps that work on raw records to the records from `open`:
+/// filtering, de-duplication, sorting and masking, then column selection,
+/// ordering and renaming.
+pub fn prepared_records<F>(
+    open: F,
+    opts: &RecordOpts,
+    rejects: &Rejects,
+) -> Result<(StringRecord, Records)>
+where
+    F: Fn(&Rejects) -> Result<(StringRecord, Records)>,
+{
+    // the scan for last occurrences has to finish before the input is reopened
+    let last_seen = match &opts.dedup {
+        Some(columns) if opts.keep_last() => {
+            let (headers, records) = filtered_records(open(&rejects.silent())?, opts)?;
+            Some(Dedup::new(&headers, columns)?.last_occurrences(records)?)
+        }
+        _ => None,
+    };
+    let (headers, mut records) = filtered_records(open(rejects)?, opts)?;
+    if let Some(columns) = &opts.dedup {
+        let dedup = Dedup::new(&headers, columns)?;
+        records = match last_seen {
+            Some(last) => dedup.keep_last(records, last),
+            None => dedup.keep_first(records),
+        };
+    }
+    if !opts.sort_by.is_empty() {
+        let keys = SortKeys::new(&headers, &opts.sort_by)?;
+        records = sort_records(records, keys, opts.sort_buffer)?;
+    }
+    if !opts.mask.is_empty() {
+        records = Masker::new(&headers, &opts.mask, opts.mask_key.as_deref())?.apply(records);
+    }
+    let projection = Projection::new(&headers, &opts.select, &opts.exclude, &opts.rename)?;
+    let headers = projection.headers().clone();
+    let records = records.map(move |r| r.map(|r| projection.apply(&r)));
+    Ok((headers, Box::new(records)))
+}
+
+fn filtered_records(
+    (headers, records): (StringRecord, Records),
+    opts: &RecordOpts,
+) -> Result<(StringRecord, Records)> {
+    let Some(expr) = &opts.filter else {
+        return Ok((headers, records));
     };
-    fs::write(output, content)?;
-    Ok(())
+    let filter = Filter::parse(expr, &headers)?;
+    let records = records.filter(move |r| r.as_ref().map_or(true, |r| filter.matches(r)));
+    Ok((headers, Box::new(records)))
+}
+
+fn infer_types(
+    separators: &[Option<String>],
+    dates: DateParser,
+    records: Records,
+) -> Result<Vec<ColumnType>> {
+    let mut inference = TypeInference::new(separators.len(), dates);
+    for record in records {
+        for (i, (field, sep)) in record?.iter().zip(separators).enumerate() {
+            match sep {
+                Some(sep) => {
+                    split_items(field, sep).for_each(|item| inference.observe_field(i, item))
+                }
+                None => inference.observe_field(i, field),
+            }
+        }
+    }
+    Ok(inference.types())
+}
+
+/// Per-column separators from `--array`; `None` for columns that are not split.
+fn array_separators(
+    headers: &StringRecord,
+    arrays: &[(String, String)],
+) -> Result<Vec<Option<String>>> {
+    let mut separators = vec![None; headers.len()];
+    for (name, sep) in arrays {
+        let idx = column_index(headers, name).context("in --array")?;
+        separators[idx] = Some(sep.clone());
+    }
+    Ok(separators)
+}
+
+fn split_items<'a>(field: &'a str, sep: &'a str) -> impl Iterator<Item = &'a str> {
+    field
+        .split(sep)
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+impl RecordConverter {
+    pub fn new(
+        headers: StringRecord,
+        inferred: Option<Vec<ColumnType>>,
+        opts: &ConversionOpts,
+    ) -> Result<Self> {
+        let types = column_types(&headers, inferred, &opts.typing.types)?;
+        let separators = array_separators(&headers, &opts.convert.arrays)?;
+        let paths = opts
+            .convert
+            .nest
+            .then(|| headers.iter().map(header_path).collect());
+        let dates = if opts.writer.format.is_columnar() {
+            // Parquet and Arrow keep dates as timestamps, read from ISO 8601
+            opts.dates.parser(None)
+        } else {
+            opts.date_parser()
+        };
+        Ok(Self {
+            headers,
+            types,
+            separators,
+            paths,
+            dates,
+        })
+    }
+
+    /// The top-level fields of the converted records. Split columns and the
+    /// objects and arrays built by `--nest` are `Nested`.
+    pub fn columns(&self) -> Vec<(String, FieldType)> {
+        let mut columns: Vec<(String, FieldType)> = Vec::with_capacity(self.headers.len());
+        for (i, header) in self.headers.iter().enumerate() {
+            let (key, nested) = match self.paths.as_ref().map(|p| p[i].as_slice()) {
+                Some([PathSegment::Key(key), rest @ ..]) => (key.as_str(), !rest.is_empty()),
+                _ => (header, false),
+            };
+            let ty = if nested || self.separators[i].is_some() {
+                FieldType::Nested
+            } else {
+                FieldType::Scalar(self.types[i])
+            };
+            match columns.iter_mut().find(|(k, _)| k == key) {
+                // later columns overwrite a flat key, and build on a nested one
+                Some((_, existing)) if self.paths.is_none() => *existing = ty,
+                Some((_, existing)) => *existing = FieldType::Nested,
+                None => columns.push((key.to_string(), ty)),
+            }
+        }
+        columns
+    }
+
+    pub fn convert(&self, record: &StringRecord) -> Result<Value> {
+        let mut values = Vec::with_capacity(self.headers.len());
+        for (i, field) in record.iter().enumerate().take(self.headers.len()) {
+            let value = match &self.separators[i] {
+                Some(sep) => split_items(field, sep)
+                    .map(|item| self.cell(i, item, record))
+                    .collect::<Result<Vec<_>>>()
+                    .map(Value::Array)?,
+                None => self.cell(i, field, record)?,
+            };
+            values.push(value);
+        }
+        match &self.paths {
+            Some(paths) => nest_record(paths.iter().map(Vec::as_slice).zip(values))
+                .with_context(|| format!("nesting line {}", line_of(record))),
+            None => {
+                let json_value: Map<String, Value> =
+                    self.headers.iter().map(String::from).zip(values).collect();
+                Ok(Value::Object(json_value))
+            }
+        }
+    }
+
+    fn cell(&self, i: usize, field: &str, record: &StringRecord) -> Result<Value> {
+        typed_value(field, self.types[i], &self.dates)
+            .with_context(|| format!("column {:?} at line {}", &self.headers[i], line_of(record)))
+    }
+}
+
+impl ConversionOpts {
+    /// `--infer`, which Parquet and Arrow output imply.
+    pub fn infers_types(&self) -> bool {
+        self.convert.infer || self.writer.format.is_columnar()
+    }
+
+    pub fn date_parser(&self) -> DateParser {
+        self.dates.parser(self.typing.date_format.clone())
+    }
+}
+
+impl RecordOpts {
+    pub fn keep_last(&self) -> bool {
+        self.dedup.is_some() && self.keep == Keep::Last
+    }
+}
+
+impl CsvDialect {
+    pub fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .comment(self.comment)
+            .flexible(self.flexible)
+            .has_headers(self.header)
+            .trim(if self.trim { Trim::All } else { Trim::None });
+        if let Some(escape) = self.escape {
+            builder.escape(Some(escape)).double_quote(false);
+        }
+        builder
+    }
+}
+
+/// Column names for the input: `--columns` if given, the header row if there
+/// is one, otherwise `col1`, `col2`… sized from the first record.
+pub fn read_headers<R: Read>(rdr: &mut Reader<R>, dialect: &CsvDialect) -> Result<StringRecord> {
+    if !dialect.columns.is_empty() {
+        return Ok(dialect.columns.iter().collect());
+    }
+    let first = rdr.headers()?;
+    if dialect.header {
+        Ok(first.clone())
+    } else {
+        Ok((1..=first.len()).map(|i| format!("col{}", i)).collect())
+    }
+}
+
+pub fn line_of(record: &StringRecord) -> u64 {
+    record.position().map_or(0, |p| p.line())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+
+    fn dialect(args: &[&str]) -> CsvDialect {
+        test_opts(args).dialect
+    }
+
+    #[test]
+    fn test_semicolon_delimiter_with_comments() -> Result<()> {
+        let d = dialect(&["-d", ";", "--comment", "#", "--trim"]);
+        let data = "# exported\nname; age\nalice; 30\n";
+        let mut rdr = d.reader_builder().from_reader(data.as_bytes());
+        let headers = read_headers(&mut rdr, &d)?;
+        assert_eq!(headers, vec!["name", "age"]);
+        let record = rdr.records().next().unwrap()?;
+        assert_eq!(record, vec!["alice", "30"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_headerless_input() -> Result<()> {
+        let d = dialect(&["--header", "false"]);
+        let mut rdr = d.reader_builder().from_reader("a,b,c\nd,e,f\n".as_bytes());
+        assert_eq!(read_headers(&mut rdr, &d)?, vec!["col1", "col2", "col3"]);
+        assert_eq!(rdr.records().count(), 2);
+
+        let d = dialect(&["--header", "false", "--columns", "x,y,z"]);
+        let mut rdr = d.reader_builder().from_reader("a,b,c\n".as_bytes());
+        assert_eq!(read_headers(&mut rdr, &d)?, vec!["x", "y", "z"]);
+        Ok(())
+    }
+
+    fn converter(headers: &[&str], args: &[&str]) -> Result<RecordConverter> {
+        RecordConverter::new(
+            StringRecord::from(headers.to_vec()),
+            None,
+            &test_opts(args).conversion,
+        )
+    }
+
+    #[test]
+    fn test_split_array_cells() -> Result<()> {
+        let converter = converter(&["Title", "Subject"], &["--array", "Subject=;"])?;
+        let record = StringRecord::from(vec!["t", "The Who; British Invasion;; AM radio"]);
+        assert_eq!(
+            converter.convert(&record)?["Subject"],
+            serde_json::json!(["The Who", "British Invasion", "AM radio"])
+        );
+        assert!(self::converter(&["Title"], &["--array", "Nope=;"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_dates() -> Result<()> {
+        let converter = converter(&["Date"], &["--type", "Date=date"])?;
+        let dates = ["6/16/16", "January 4 1965", "12/1/93"].map(|d| {
+            converter
+                .convert(&StringRecord::from(vec![d]))
+                .map(|v| v["Date"].clone())
+        });
+        let dates = dates.into_iter().collect::<Result<Vec<_>>>()?;
+        assert_eq!(dates, ["2016-06-16", "1965-01-04", "1993-12-01"]);
+        // like any other bad value, left to --on-error
+        assert!(
+            converter
+                .convert(&StringRecord::from(vec!["someday"]))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_char() -> Result<()> {
+        let d = dialect(&["--escape", "\\"]);
+        let mut rdr = d
+            .reader_builder()
+            .from_reader("a\n\"say \\\"hi\\\"\"\n".as_bytes());
+        read_headers(&mut rdr, &d)?;
+        let record = rdr.records().next().unwrap()?;
+        assert_eq!(&record[0], "say \"hi\"");
+        Ok(())
+    }
 }
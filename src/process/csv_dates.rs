@@ -0,0 +1,205 @@
+use crate::cli::DateOpts;
+use anyhow::{Result, anyhow};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use std::fmt::Write;
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Parses the date spellings commonly found in exports (`6/16/16`,
+/// `2016-06-16`, `January 4 1965`, `4 Jan 1965`…) and prints them in one format.
+#[derive(Debug, Clone)]
+pub struct DateParser {
+    year_pivot: u32,
+    day_first: bool,
+    format: Option<String>,
+}
+
+impl DateParser {
+    /// Two-digit years below `year_pivot` are read as 20xx, the rest as 19xx.
+    /// `format` is a strftime string; ISO-8601 is used when it is `None`.
+    pub fn new(year_pivot: u32, day_first: bool, format: Option<String>) -> Self {
+        Self {
+            year_pivot,
+            day_first,
+            format,
+        }
+    }
+
+    pub fn parse(&self, s: &str) -> Option<NaiveDateTime> {
+        let s = s.trim();
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+            return Some(dt);
+        }
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f") {
+            return Some(dt);
+        }
+        self.parse_date(s).map(|d| d.and_time(NaiveTime::MIN))
+    }
+
+    pub fn normalize(&self, s: &str) -> Result<String> {
+        let dt = self
+            .parse(s)
+            .ok_or_else(|| anyhow!("Cannot parse {:?} as date", s))?;
+        let format = match &self.format {
+            Some(format) => format.as_str(),
+            None if dt.time() == NaiveTime::MIN => "%Y-%m-%d",
+            None => "%Y-%m-%dT%H:%M:%S",
+        };
+        format_date(&dt, format)
+    }
+
+    fn parse_date(&self, s: &str) -> Option<NaiveDate> {
+        let tokens: Vec<&str> = s
+            .split(|c: char| c.is_whitespace() || matches!(c, ',' | '/' | '-' | '.'))
+            .filter(|t| !t.is_empty())
+            .collect();
+        if tokens.len() != 3 {
+            return None;
+        }
+        if let Some(i) = tokens.iter().position(|t| month_number(t).is_some()) {
+            // January 4 1965, 4 January 1965, 1965 Jan 4
+            let month = month_number(tokens[i])?;
+            let rest: Vec<&str> = tokens
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, t)| *t)
+                .collect();
+            let (day, year) = if rest[0].len() == 4 {
+                (rest[1], rest[0])
+            } else {
+                (rest[0], rest[1])
+            };
+            return self.ymd(year, month, number(day)?);
+        }
+        let (a, b, c) = (tokens[0], tokens[1], tokens[2]);
+        if a.len() == 4 {
+            // zero-padding required, so identifiers like 2016.1.1 are not dates
+            if b.len() != 2 || c.len() != 2 {
+                return None;
+            }
+            return self.ymd(a, number(b)?, number(c)?);
+        }
+        let (month, day) = if self.day_first { (b, a) } else { (a, b) };
+        self.ymd(c, number(month)?, number(day)?)
+    }
+
+    fn ymd(&self, year: &str, month: u32, day: u32) -> Option<NaiveDate> {
+        let value = number(year)?;
+        let year = match year.len() {
+            4 => value,
+            2 if value < self.year_pivot => 2000 + value,
+            2 => 1900 + value,
+            _ => return None,
+        };
+        NaiveDate::from_ymd_opt(year as i32, month, day)
+    }
+}
+
+impl DateOpts {
+    /// A parser for these options that prints dates with `format`.
+    pub fn parser(&self, format: Option<String>) -> DateParser {
+        DateParser::new(self.year_pivot, self.day_first, format)
+    }
+}
+
+fn number(s: &str) -> Option<u32> {
+    if s.is_empty() || s.len() > 4 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Format `dt`, failing rather than panicking on specifiers such as `%z`
+/// that a date without a time zone cannot fill.
+pub fn format_date(dt: &NaiveDateTime, format: &str) -> Result<String> {
+    let mut out = String::new();
+    write!(out, "{}", dt.format(format))
+        .map_err(|_| anyhow!("Cannot format a date with {:?}", format))?;
+    Ok(out)
+}
+
+fn month_number(s: &str) -> Option<u32> {
+    let s = s.to_ascii_lowercase();
+    if s.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|m| m.starts_with(&s) && (s.len() == 3 || s.len() == m.len() || s == "sept"))
+        .map(|i| i as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_dates() {
+        let parser = DateParser::new(50, false, None);
+        assert_eq!(
+            parser.normalize("6/16/16").ok().as_deref(),
+            Some("2016-06-16")
+        );
+        assert_eq!(
+            parser.normalize("January 4 1965").ok().as_deref(),
+            Some("1965-01-04")
+        );
+        assert_eq!(
+            parser.normalize("12/1/93").ok().as_deref(),
+            Some("1993-12-01")
+        );
+        assert_eq!(
+            parser.normalize("4 Jan, 1965").ok().as_deref(),
+            Some("1965-01-04")
+        );
+        assert_eq!(
+            parser.normalize("2016-06-16").ok().as_deref(),
+            Some("2016-06-16")
+        );
+        assert_eq!(
+            parser.normalize("2016-06-16 08:30:00").ok().as_deref(),
+            Some("2016-06-16T08:30:00")
+        );
+    }
+
+    #[test]
+    fn test_year_pivot_and_day_first() {
+        let parser = DateParser::new(20, true, Some("%d.%m.%Y".to_string()));
+        assert_eq!(
+            parser.normalize("16/6/16").ok().as_deref(),
+            Some("16.06.2016")
+        );
+        assert_eq!(
+            parser.normalize("1/2/25").ok().as_deref(),
+            Some("01.02.1925")
+        );
+    }
+
+    #[test]
+    fn test_unparseable_dates() {
+        let parser = DateParser::new(50, false, None);
+        assert!(parser.parse("13/45/2020").is_none());
+        assert!(parser.parse("2016.1.1.2").is_none());
+        assert!(parser.parse("Farm subsidies").is_none());
+        assert!(parser.parse("2016.1").is_none());
+        assert!(parser.parse("2016.1.1").is_none());
+        assert!(parser.parse("Mayday 4 1965").is_none());
+        assert!(parser.normalize("someday").is_err());
+        let zoned = DateParser::new(50, false, Some("%Y %z".to_string()));
+        assert!(zoned.normalize("6/16/16").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use crate::cli::{CsvOpts, DiffOpts, OutputFormat};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_convert::line_of;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_source::{Input, Records};
+use crate::process::csv_writer::{RecordWriter, open_output, record_writer};
+use anyhow::{Context, Result, bail};
+use csv::StringRecord;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::io::BufWriter;
+
+/// `rcli csv diff`: the old file is held in memory by key and the new one is
+/// streamed past it. Columns are matched by name; one missing from a file
+/// reads as empty there.
+pub fn process_csv_diff(output: &str, opts: &CsvOpts, diff: &DiffOpts) -> Result<()> {
+    if diff.old == "-" && diff.new == "-" {
+        bail!("Only one side of a diff can be read from stdin");
+    }
+    let rejects = Rejects::new(opts);
+    let old = Input::new(&diff.old, false)?;
+    let new = Input::new(&diff.new, false)?;
+    let (old_headers, old_records) = old.records(opts, &rejects)?;
+    let mut table = KeyedTable::load(&old_headers, old_records, &diff.key)
+        .with_context(|| format!("reading {}", diff.old))?;
+    let (new_headers, new_records) = new.records(opts, &rejects)?;
+    let differ = Differ::new(&old_headers, &new_headers, &diff.key)?;
+
+    let long = matches!(
+        diff.writer.format,
+        OutputFormat::Table | OutputFormat::Csv | OutputFormat::Markdown | OutputFormat::Html
+    );
+    let output =
+        open_output(output, &diff.writer).with_context(|| format!("creating {}", output))?;
+    let mut writer = record_writer(&diff.writer, BufWriter::new(output));
+    let mut counts = [0; 3];
+    let mut seen_new = HashMap::new();
+    for record in new_records {
+        let record = record?;
+        let key = differ.new_key(&record);
+        if let Some(line) = seen_new.insert(key.clone(), line_of(&record)) {
+            bail!(
+                "Duplicate key {:?} in {} at lines {} and {}",
+                key,
+                diff.new,
+                line,
+                line_of(&record)
+            );
+        }
+        let change = match table.take(&key) {
+            None => differ.change(Status::Added, &key, None, Some(&record)),
+            Some(old) => differ.change(Status::Changed, &key, Some(&old), Some(&record)),
+        };
+        if let Some(change) = change {
+            counts[change.status as usize] += 1;
+            change.write(writer.as_mut(), long)?;
+        }
+    }
+    for old in table.remaining() {
+        let key = differ.old_key(old);
+        if let Some(change) = differ.change(Status::Removed, &key, Some(old), None) {
+            counts[change.status as usize] += 1;
+            change.write(writer.as_mut(), long)?;
+        }
+    }
+    writer.finish()?;
+    rejects.finish()?;
+    eprintln!(
+        "{} added, {} removed, {} changed",
+        counts[0], counts[1], counts[2]
+    );
+    Ok(())
+}
+
+/// The old file's records, by key, in file order.
+struct KeyedTable {
+    records: Vec<Option<StringRecord>>,
+    index: HashMap<Vec<String>, usize>,
+}
+
+impl KeyedTable {
+    fn load(headers: &StringRecord, records: Records, key: &[String]) -> Result<Self> {
+        let columns = key
+            .iter()
+            .map(|c| column_index(headers, c).context("in --key"))
+            .collect::<Result<Vec<_>>>()?;
+        let mut table = Self {
+            records: Vec::new(),
+            index: HashMap::new(),
+        };
+        for record in records {
+            let record = record?;
+            let key = key_of(&record, &columns);
+            if let Some(&i) = table.index.get(&key) {
+                let first = table.records[i].as_ref().map_or(0, line_of);
+                bail!(
+                    "Duplicate key {:?} at lines {} and {}",
+                    key,
+                    first,
+                    line_of(&record)
+                );
+            }
+            table.index.insert(key, table.records.len());
+            table.records.push(Some(record));
+        }
+        Ok(table)
+    }
+
+    fn take(&mut self, key: &[String]) -> Option<StringRecord> {
+        let i = *self.index.get(key)?;
+        self.records[i].take()
+    }
+
+    fn remaining(&self) -> impl Iterator<Item = &StringRecord> {
+        self.records.iter().flatten()
+    }
+}
+
+fn key_of(record: &StringRecord, columns: &[usize]) -> Vec<String> {
+    columns
+        .iter()
+        .map(|&i| record.get(i).unwrap_or("").to_string())
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Changed => "changed",
+        }
+    }
+}
+
+/// Compares records of the two files column by column over the union of
+/// their headers.
+struct Differ {
+    key: Vec<String>,
+    columns: Vec<(String, Option<usize>, Option<usize>)>,
+    old_key: Vec<usize>,
+    new_key: Vec<usize>,
+}
+
+/// One differing row: `(column, old, new)` for every column that differs.
+#[derive(Debug, PartialEq)]
+struct Change {
+    status: Status,
+    key: Vec<(String, String)>,
+    columns: Vec<(String, String, String)>,
+}
+
+impl Differ {
+    fn new(old: &StringRecord, new: &StringRecord, key: &[String]) -> Result<Self> {
+        let mut columns: Vec<(String, Option<usize>, Option<usize>)> = old
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), Some(i), None))
+            .collect();
+        for (i, name) in new.iter().enumerate() {
+            match columns.iter_mut().find(|(n, _, _)| n == name) {
+                Some(column) => column.2 = Some(i),
+                None => columns.push((name.to_string(), None, Some(i))),
+            }
+        }
+        let resolve = |headers: &StringRecord| {
+            key.iter()
+                .map(|c| column_index(headers, c).context("in --key"))
+                .collect::<Result<Vec<_>>>()
+        };
+        let old_key = resolve(old)?;
+        let new_key = resolve(new)?;
+        let is_key = |o: &Option<usize>, n: &Option<usize>| {
+            o.is_some_and(|o| old_key.contains(&o)) || n.is_some_and(|n| new_key.contains(&n))
+        };
+        columns.retain(|(_, o, n)| !is_key(o, n));
+        Ok(Self {
+            key: old_key.iter().map(|&i| old[i].to_string()).collect(),
+            columns,
+            old_key,
+            new_key,
+        })
+    }
+
+    fn old_key(&self, record: &StringRecord) -> Vec<String> {
+        key_of(record, &self.old_key)
+    }
+
+    fn new_key(&self, record: &StringRecord) -> Vec<String> {
+        key_of(record, &self.new_key)
+    }
+
+    /// Changed rows list the columns that differ, added and removed rows all
+    /// of them. `None` when a pair of records is identical.
+    fn change(
+        &self,
+        status: Status,
+        key: &[String],
+        old: Option<&StringRecord>,
+        new: Option<&StringRecord>,
+    ) -> Option<Change> {
+        let field = |record: Option<&StringRecord>, i: Option<usize>| {
+            record
+                .zip(i)
+                .and_then(|(r, i)| r.get(i))
+                .unwrap_or("")
+                .to_string()
+        };
+        let columns: Vec<(String, String, String)> = self
+            .columns
+            .iter()
+            .map(|(name, o, n)| (name.clone(), field(old, *o), field(new, *n)))
+            .filter(|(_, old, new)| status != Status::Changed || old != new)
+            .collect();
+        if status == Status::Changed && columns.is_empty() {
+            return None;
+        }
+        Some(Change {
+            status,
+            key: self.key.iter().cloned().zip(key.iter().cloned()).collect(),
+            columns,
+        })
+    }
+}
+
+impl Change {
+    /// Nested: one record per row, with `{old, new}` for changed columns and
+    /// the whole row for added or removed ones. Long: one record per column,
+    /// which reads better in a table.
+    fn write(&self, writer: &mut dyn RecordWriter, long: bool) -> Result<()> {
+        let mut head = Map::new();
+        head.insert("status".to_string(), json!(self.status.name()));
+        for (name, value) in &self.key {
+            head.insert(name.clone(), json!(value));
+        }
+        if long {
+            for (column, old, new) in &self.columns {
+                let mut record = head.clone();
+                record.insert("column".to_string(), json!(column));
+                record.insert("old".to_string(), json!(old));
+                record.insert("new".to_string(), json!(new));
+                writer.write_record(&Value::Object(record))?;
+            }
+            return Ok(());
+        }
+        let mut record = head;
+        let (name, values): (&str, Map<String, Value>) = match self.status {
+            Status::Changed => (
+                "changes",
+                self.columns
+                    .iter()
+                    .map(|(c, old, new)| (c.clone(), json!({"old": old, "new": new})))
+                    .collect(),
+            ),
+            Status::Added => (
+                "record",
+                self.columns
+                    .iter()
+                    .map(|(c, _, new)| (c.clone(), json!(new)))
+                    .collect(),
+            ),
+            Status::Removed => (
+                "record",
+                self.columns
+                    .iter()
+                    .map(|(c, old, _)| (c.clone(), json!(old)))
+                    .collect(),
+            ),
+        };
+        record.insert(name.to_string(), Value::Object(values));
+        writer.write_record(&Value::Object(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    fn differ() -> Differ {
+        let old = record(&["Identifier", "Title", "Notes"]);
+        let new = record(&["Title", "Identifier", "Creator"]);
+        Differ::new(&old, &new, &["Identifier".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_changed_columns() {
+        let differ = differ();
+        let old = record(&["1", "Title", "n"]);
+        let new = record(&["New title", "1", ""]);
+        let change = differ
+            .change(
+                Status::Changed,
+                &differ.new_key(&new),
+                Some(&old),
+                Some(&new),
+            )
+            .unwrap();
+        assert_eq!(change.key, [("Identifier".to_string(), "1".to_string())]);
+        let columns: Vec<_> = change
+            .columns
+            .iter()
+            .map(|(c, o, n)| (c.as_str(), o.as_str(), n.as_str()))
+            .collect();
+        assert_eq!(
+            columns,
+            [("Title", "Title", "New title"), ("Notes", "n", "")]
+        );
+
+        let same = record(&["Title", "1", ""]);
+        let old = record(&["1", "Title", ""]);
+        assert!(
+            differ
+                .change(Status::Changed, &["1".to_string()], Some(&old), Some(&same))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_added_and_removed() {
+        let differ = differ();
+        let added = differ
+            .change(
+                Status::Added,
+                &["2".to_string()],
+                None,
+                Some(&record(&["t", "2", "c"])),
+            )
+            .unwrap();
+        assert_eq!(added.columns.len(), 3);
+        let removed = differ
+            .change(
+                Status::Removed,
+                &["3".to_string()],
+                Some(&record(&["3", "t", ""])),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            removed.columns[0],
+            ("Title".to_string(), "t".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let headers = record(&["id"]);
+        let rows: Records = Box::new(vec![Ok(record(&["1"])), Ok(record(&["1"]))].into_iter());
+        assert!(KeyedTable::load(&headers, rows, &["id".to_string()]).is_err());
+    }
+}
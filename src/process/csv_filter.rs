@@ -0,0 +1,385 @@
+use crate::process::csv_columns::column_index;
+use anyhow::{Result, anyhow, bail};
+use csv::StringRecord;
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// A compiled `--where` expression, e.g.
+/// `Creator == "John Doe" && Notes != "" && Year in 1990..2000`.
+///
+/// Columns are bare identifiers or `backtick quoted`; literals are quoted
+/// strings or numbers. Supported: `== != < <= > >=`, `contains`,
+/// `startswith`, `endswith`, `~`/`!~` (regex), `in A..B`, `is [not] null`,
+/// `&&`/`and`, `||`/`or`, `!`/`not` and parentheses. Comparisons are numeric
+/// when both sides are numbers and lexical otherwise; empty cells are null.
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CmpOp, Operand),
+    Matches(Operand, Regex),
+    Range(Operand, f64, f64),
+    IsNull(Operand),
+}
+
+#[derive(Debug)]
+enum Operand {
+    Column(usize),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Column(String),
+    Str(String),
+    Num(String),
+    Op(&'static str),
+}
+
+const OPS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "!~", "..", "<", ">", "!", "~", "=", "(", ")",
+];
+
+impl Filter {
+    pub fn parse(source: &str, headers: &StringRecord) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            headers,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {:?} in --where expression", token);
+        }
+        Ok(Self { expr })
+    }
+
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.expr.eval(record)
+    }
+}
+
+impl Expr {
+    fn eval(&self, record: &StringRecord) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(record) || b.eval(record),
+            Expr::And(a, b) => a.eval(record) && b.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::IsNull(operand) => operand.value(record).is_empty(),
+            Expr::Matches(operand, re) => re.is_match(operand.value(record)),
+            Expr::Range(operand, lo, hi) => operand
+                .value(record)
+                .trim()
+                .parse::<f64>()
+                .is_ok_and(|v| *lo <= v && v <= *hi),
+            Expr::Compare(left, op, right) => {
+                let (l, r) = (left.value(record), right.value(record));
+                match op {
+                    CmpOp::Contains => l.contains(r),
+                    CmpOp::StartsWith => l.starts_with(r),
+                    CmpOp::EndsWith => l.ends_with(r),
+                    CmpOp::Eq => compare(l, r) == Ordering::Equal,
+                    CmpOp::Ne => compare(l, r) != Ordering::Equal,
+                    CmpOp::Lt => compare(l, r) == Ordering::Less,
+                    CmpOp::Le => compare(l, r) != Ordering::Greater,
+                    CmpOp::Gt => compare(l, r) == Ordering::Greater,
+                    CmpOp::Ge => compare(l, r) != Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn value<'a>(&'a self, record: &'a StringRecord) -> &'a str {
+        match self {
+            Operand::Column(i) => record.get(*i).unwrap_or(""),
+            Operand::Literal(s) => s,
+        }
+    }
+}
+
+fn compare(l: &str, r: &str) -> Ordering {
+    match (l.trim().parse::<f64>(), r.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => l.cmp(r),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    headers: &'a StringRecord,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of --where expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Op(o)) => *o == op,
+            Some(Token::Ident(word)) => word.eq_ignore_ascii_case(op),
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") || self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat("&&") || self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("!") || self.eat("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                bail!("Missing ')' in --where expression");
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.operand()?;
+        if self.eat("is") {
+            let negated = self.eat("not");
+            if !self.eat("null") {
+                bail!("Expected 'null' after 'is' in --where expression");
+            }
+            let expr = Expr::IsNull(left);
+            return Ok(if negated {
+                Expr::Not(Box::new(expr))
+            } else {
+                expr
+            });
+        }
+        if self.eat("in") {
+            let lo = self.number()?;
+            if !self.eat("..") {
+                bail!("Expected a range like 1..10 after 'in'");
+            }
+            return Ok(Expr::Range(left, lo, self.number()?));
+        }
+        for (word, negated) in [("~", false), ("matches", false), ("!~", true)] {
+            if self.eat(word) {
+                let pattern = match self.next()? {
+                    Token::Str(s) => s,
+                    token => bail!("Expected a quoted regex, got {:?}", token),
+                };
+                let expr = Expr::Matches(left, Regex::new(&pattern)?);
+                return Ok(if negated {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                });
+            }
+        }
+        let op = match self.next()? {
+            Token::Op("==" | "=") => CmpOp::Eq,
+            Token::Op("!=") => CmpOp::Ne,
+            Token::Op("<") => CmpOp::Lt,
+            Token::Op("<=") => CmpOp::Le,
+            Token::Op(">") => CmpOp::Gt,
+            Token::Op(">=") => CmpOp::Ge,
+            Token::Ident(w) if w.eq_ignore_ascii_case("contains") => CmpOp::Contains,
+            Token::Ident(w) if w.eq_ignore_ascii_case("startswith") => CmpOp::StartsWith,
+            Token::Ident(w) if w.eq_ignore_ascii_case("endswith") => CmpOp::EndsWith,
+            token => bail!("Expected a comparison operator, got {:?}", token),
+        };
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next()? {
+            Token::Str(s) | Token::Num(s) => Ok(Operand::Literal(s)),
+            Token::Ident(name) | Token::Column(name) => {
+                Ok(Operand::Column(column_index(self.headers, &name)?))
+            }
+            token => bail!("Expected a column or value, got {:?}", token),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64> {
+        match self.next()? {
+            Token::Num(n) => Ok(n.parse()?),
+            token => bail!("Expected a number, got {:?}", token),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '\'' || c == '`' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!("Unterminated {} in --where expression", c),
+                    Some('\\') if c != '`' && i + 1 < chars.len() => {
+                        text.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&ch) => {
+                        text.push(ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(if c == '`' {
+                Token::Column(text)
+            } else {
+                Token::Str(text)
+            });
+        } else if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() {
+                let fraction =
+                    chars[i] == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit());
+                if !(chars[i].is_ascii_digit() || fraction) {
+                    break;
+                }
+                i += if fraction { 2 } else { 1 };
+            }
+            tokens.push(Token::Num(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.')) {
+                if chars[i] == '.' && chars.get(i + 1) == Some(&'.') {
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| anyhow!("Unexpected '{}' in --where expression", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> StringRecord {
+        StringRecord::from(vec!["Title", "Creator", "Notes", "Accession No"])
+    }
+
+    fn matches(expr: &str, row: &[&str]) -> Result<bool> {
+        Ok(Filter::parse(expr, &headers())?.matches(&StringRecord::from(row.to_vec())))
+    }
+
+    #[test]
+    fn test_comparisons_and_combinators() -> Result<()> {
+        let row = ["My Title", "John Doe", "", "2016.1"];
+        assert!(matches(r#"Creator == "John Doe" && Notes == """#, &row)?);
+        assert!(!matches(r#"Creator == "John Doe" && Notes != """#, &row)?);
+        assert!(matches(
+            r#"Creator == 'Mary' || Title contains "Title""#,
+            &row
+        )?);
+        assert!(matches(r#"not (Creator startswith "Mary")"#, &row)?);
+        assert!(matches(
+            "`Accession No` > 2015.5 and `Accession No` <= 2016.1",
+            &row
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_regex_range_and_null() -> Result<()> {
+        let row = ["The best image ever", "Mary Maryson", "", "2015.4"];
+        assert!(matches(r#"Title ~ "^The \\w+""#, &row)?);
+        assert!(matches(r#"Creator !~ "Doe$""#, &row)?);
+        assert!(matches("`Accession No` in 2015..2016", &row)?);
+        assert!(!matches("`Accession No` in 2016..2020", &row)?);
+        assert!(matches("Notes is null && Title is not null", &row)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_numeric_versus_lexical_order() -> Result<()> {
+        let row = ["10", "9", "", ""];
+        assert!(matches("Title > Creator", &row)?);
+        assert!(matches(r#"Title < "9a""#, &row)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Filter::parse("Missing == 1", &headers()).is_err());
+        assert!(Filter::parse("Title ==", &headers()).is_err());
+        assert!(Filter::parse("(Title == 1", &headers()).is_err());
+        assert!(Filter::parse("Title == \"x", &headers()).is_err());
+        assert!(Filter::parse("Title ~ \"(\"", &headers()).is_err());
+    }
+}
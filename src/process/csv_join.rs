@@ -0,0 +1,283 @@
+use crate::cli::{CsvOpts, JoinKind, JoinOpts};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_convert::convert_records;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_source::{Input, Records};
+use anyhow::{Context, Result, bail};
+use csv::StringRecord;
+use std::collections::{HashMap, VecDeque};
+
+/// `rcli csv join`: a hash join that holds the right file in memory and
+/// streams the left one. The joined records go through the same steps as a
+/// conversion, so `--where`, `--select`, `--sort-by` and the rest apply.
+pub fn process_csv_join(output: &str, opts: &CsvOpts, join: &JoinOpts) -> Result<()> {
+    if join.left == "-" && join.right == "-" {
+        bail!("Only one side of a join can be read from stdin");
+    }
+    let rereads = join.conversion.infers_types() || join.conversion.records.keep_last();
+    let left = Input::new(&join.left, rereads)?;
+    let right = Input::new(&join.right, rereads)?;
+    let open = |rejects: &_| {
+        let (right_headers, right_records) = right.records(opts, rejects)?;
+        let right_records = right_records.collect::<Result<Vec<_>>>()?;
+        let (left_headers, left_records) = left.records(opts, rejects)?;
+        let joiner = Joiner::new(&left_headers, &right_headers, join)?;
+        Ok((
+            joiner.headers.clone(),
+            joiner.join(left_records, right_records, join.how),
+        ))
+    };
+    let rejects = Rejects::new(opts);
+    convert_records(open, output, &join.conversion, &rejects)?;
+    rejects.finish()
+}
+
+/// Where the key columns are on each side and how joined records are laid
+/// out: every left column, then the right columns that are not keys.
+#[derive(Debug)]
+struct Joiner {
+    headers: StringRecord,
+    left_keys: Vec<usize>,
+    right_keys: Vec<usize>,
+    right_rest: Vec<usize>,
+    left_width: usize,
+}
+
+impl Joiner {
+    fn new(left: &StringRecord, right: &StringRecord, opts: &JoinOpts) -> Result<Self> {
+        let mut left_keys = Vec::new();
+        let mut right_keys = Vec::new();
+        for (l, r) in &opts.on {
+            left_keys.push(column_index(left, l).context("in --on, left side")?);
+            right_keys.push(column_index(right, r).context("in --on, right side")?);
+        }
+        let right_rest: Vec<usize> = (0..right.len())
+            .filter(|i| !right_keys.contains(i))
+            .collect();
+        let rest_names: Vec<&str> = right_rest.iter().map(|&i| &right[i]).collect();
+        let mut headers: Vec<String> = left
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                if !left_keys.contains(&i) && rest_names.contains(&name) {
+                    format!("{}{}", opts.left_prefix, name)
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect();
+        for name in rest_names {
+            if left.iter().any(|h| h == name) {
+                headers.push(format!("{}{}", opts.right_prefix, name));
+            } else {
+                headers.push(name.to_string());
+            }
+        }
+        Ok(Self {
+            headers: StringRecord::from(headers),
+            left_keys,
+            right_keys,
+            right_rest,
+            left_width: left.len(),
+        })
+    }
+
+    fn join(self, left: Records, right: Vec<StringRecord>, kind: JoinKind) -> Records {
+        let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+        for (i, record) in right.iter().enumerate() {
+            index
+                .entry(key(record, &self.right_keys))
+                .or_default()
+                .push(i);
+        }
+        Box::new(JoinedRecords {
+            matched: vec![false; right.len()],
+            joiner: self,
+            left,
+            right,
+            index,
+            kind,
+            pending: VecDeque::new(),
+            unmatched: 0,
+        })
+    }
+
+    /// A joined record; the key columns of a right record without a match
+    /// go where the left ones would be.
+    fn record(&self, left: Option<&StringRecord>, right: Option<&StringRecord>) -> StringRecord {
+        let mut fields: Vec<&str> = match (left, right) {
+            (Some(left), _) => (0..self.left_width)
+                .map(|i| left.get(i).unwrap_or(""))
+                .collect(),
+            (None, Some(right)) => {
+                let mut fields = vec![""; self.left_width];
+                for (&l, &r) in self.left_keys.iter().zip(&self.right_keys) {
+                    fields[l] = right.get(r).unwrap_or("");
+                }
+                fields
+            }
+            (None, None) => vec![""; self.left_width],
+        };
+        fields.extend(
+            self.right_rest
+                .iter()
+                .map(|&i| right.and_then(|r| r.get(i)).unwrap_or("")),
+        );
+        let mut record = StringRecord::from(fields);
+        record.set_position(left.or(right).and_then(|r| r.position()).cloned());
+        record
+    }
+}
+
+fn key(record: &StringRecord, columns: &[usize]) -> Vec<String> {
+    columns
+        .iter()
+        .map(|&i| record.get(i).unwrap_or("").to_string())
+        .collect()
+}
+
+struct JoinedRecords {
+    joiner: Joiner,
+    left: Records,
+    right: Vec<StringRecord>,
+    index: HashMap<Vec<String>, Vec<usize>>,
+    kind: JoinKind,
+    matched: Vec<bool>,
+    pending: VecDeque<StringRecord>,
+    /// Next right record to check once the left side is exhausted.
+    unmatched: usize,
+}
+
+impl Iterator for JoinedRecords {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Some(Ok(record));
+            }
+            match self.left.next() {
+                Some(Ok(left)) => match self.index.get(&key(&left, &self.joiner.left_keys)) {
+                    Some(matches) => {
+                        for &i in matches {
+                            self.matched[i] = true;
+                            let record = self.joiner.record(Some(&left), Some(&self.right[i]));
+                            self.pending.push_back(record);
+                        }
+                    }
+                    None if matches!(self.kind, JoinKind::Left | JoinKind::Full) => {
+                        self.pending
+                            .push_back(self.joiner.record(Some(&left), None));
+                    }
+                    None => {}
+                },
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+        if !matches!(self.kind, JoinKind::Right | JoinKind::Full) {
+            return None;
+        }
+        while self.unmatched < self.right.len() {
+            let i = self.unmatched;
+            self.unmatched += 1;
+            if !self.matched[i] {
+                return Some(Ok(self.joiner.record(None, Some(&self.right[i]))));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvSubCommand;
+    use crate::cli::test_opts;
+
+    const ANY_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+
+    fn join_opts(args: &[&str]) -> JoinOpts {
+        // only parsed, so any existing file will do for the sides
+        match test_opts(&[&["join", ANY_FILE, ANY_FILE], args].concat()).cmd {
+            Some(CsvSubCommand::Join(opts)) => opts,
+            _ => unreachable!(),
+        }
+    }
+
+    fn join(args: &[&str]) -> Result<(StringRecord, Vec<StringRecord>)> {
+        let opts = join_opts(args);
+        let left = StringRecord::from(vec!["Identifier", "Title", "Notes"]);
+        let right = StringRecord::from(vec!["Object", "Notes", "Accession"]);
+        let left_rows: Vec<StringRecord> = [["1", "a", "x"], ["2", "b", "y"], ["3", "c", "z"]]
+            .iter()
+            .map(|r| StringRecord::from(r.to_vec()))
+            .collect();
+        let right_rows: Vec<StringRecord> = [
+            ["3", "n3", "gift"],
+            ["4", "n4", "loan"],
+            ["1", "n1", "buy"],
+            ["1", "n1b", "buy"],
+        ]
+        .iter()
+        .map(|r| StringRecord::from(r.to_vec()))
+        .collect();
+        let joiner = Joiner::new(&left, &right, &opts)?;
+        let headers = joiner.headers.clone();
+        let records = joiner.join(
+            Box::new(left_rows.into_iter().map(Ok)),
+            right_rows,
+            opts.how,
+        );
+        Ok((headers, records.collect::<Result<_>>()?))
+    }
+
+    fn ids(records: &[StringRecord]) -> Vec<(&str, &str)> {
+        records.iter().map(|r| (&r[0], &r[3])).collect()
+    }
+
+    #[test]
+    fn test_join_kinds() -> Result<()> {
+        let (headers, records) = join(&["--on", "Identifier=Object"])?;
+        assert_eq!(
+            headers,
+            vec![
+                "Identifier",
+                "Title",
+                "left_Notes",
+                "right_Notes",
+                "Accession"
+            ]
+        );
+        assert_eq!(ids(&records), [("1", "n1"), ("1", "n1b"), ("3", "n3")]);
+        assert_eq!(records[0], vec!["1", "a", "x", "n1", "buy"]);
+
+        let (_, records) = join(&["--on", "Identifier=Object", "--how", "left"])?;
+        assert_eq!(
+            ids(&records),
+            [("1", "n1"), ("1", "n1b"), ("2", ""), ("3", "n3")]
+        );
+
+        let (_, records) = join(&["--on", "Identifier=Object", "--how", "right"])?;
+        assert_eq!(
+            ids(&records),
+            [("1", "n1"), ("1", "n1b"), ("3", "n3"), ("4", "n4")]
+        );
+        assert_eq!(records[3], vec!["4", "", "", "n4", "loan"]);
+
+        let (_, records) = join(&["--on", "Identifier=Object", "--how", "full"])?;
+        assert_eq!(records.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefixes_and_bad_keys() -> Result<()> {
+        let (headers, _) = join(&["--on", "1=1", "--left-prefix", "l.", "--right-prefix", "r."])?;
+        assert_eq!(
+            headers,
+            vec!["Identifier", "Title", "l.Notes", "r.Notes", "Accession"]
+        );
+        assert!(join(&["--on", "Identifier"]).is_err());
+        Ok(())
+    }
+}
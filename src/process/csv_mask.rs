@@ -0,0 +1,187 @@
+use crate::cli::{FakeKind, Mask, MaskRule};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_source::Records;
+use crate::process::text::{Blake3, KeyLoader, TestSign};
+use anyhow::{Context, Result, bail};
+use csv::StringRecord;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::sync::OnceLock;
+
+/// The key of fake data without `--mask-key`, shared by every input of a run.
+fn run_key() -> &'static [u8; 32] {
+    static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key
+    })
+}
+
+const FIRST_NAMES: [&str; 16] = [
+    "Alex", "Blair", "Casey", "Dana", "Eden", "Finley", "Gray", "Harper", "Indy", "Jordan", "Kai",
+    "Logan", "Morgan", "Noel", "Quinn", "Riley",
+];
+
+const LAST_NAMES: [&str; 16] = [
+    "Adams", "Baker", "Chen", "Diaz", "Evans", "Fischer", "Garcia", "Hughes", "Ito", "Jensen",
+    "Kowalski", "Lopez", "Moreau", "Novak", "Okafor", "Patel",
+];
+
+/// `--mask`: scrubs the values of some columns. Empty values stay empty.
+///
+/// Hashes and fake data are derived from the value, so the same value always
+/// gets the same replacement. The derivation is a keyed BLAKE3 hash that
+/// cannot be reversed without the key. `hash` needs `--mask-key`, so that
+/// runs agree; without one, fake data uses a random key of this run, and
+/// the same value gets a different fake in the next run.
+pub struct Masker {
+    masks: Vec<Option<Mask>>,
+    key: Blake3,
+}
+
+impl Masker {
+    pub fn new(headers: &StringRecord, rules: &[MaskRule], key: Option<&str>) -> Result<Self> {
+        let mut masks = vec![None; headers.len()];
+        for rule in rules {
+            let idx = column_index(headers, &rule.column).context("in --mask")?;
+            masks[idx] = Some(rule.mask.clone());
+        }
+        let key = match key {
+            Some(path) => {
+                Blake3::load(path).with_context(|| format!("loading --mask-key {}", path))?
+            }
+            None if masks.contains(&Some(Mask::Hash)) => bail!("--mask hash needs a --mask-key"),
+            None => Blake3::new(*run_key()),
+        };
+        Ok(Self { masks, key })
+    }
+
+    fn digest(&self, value: &str) -> Result<Vec<u8>> {
+        self.key.sign(&mut value.as_bytes())
+    }
+
+    fn mask_value(&self, mask: &Mask, value: &str) -> Result<String> {
+        if value.is_empty() {
+            return Ok(String::new());
+        }
+        let masked = match mask {
+            Mask::Redact(text) => text.clone(),
+            Mask::Truncate(n) => value.chars().take(*n).collect(),
+            Mask::Hash => self.digest(value)?[..16]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            Mask::Fake(kind) => fake_value(*kind, &self.digest(value)?),
+        };
+        Ok(masked)
+    }
+
+    pub fn mask(&self, record: &StringRecord) -> Result<StringRecord> {
+        let mut masked = StringRecord::with_capacity(record.as_slice().len(), record.len());
+        for (field, mask) in record
+            .iter()
+            .zip(self.masks.iter().chain(std::iter::repeat(&None)))
+        {
+            match mask {
+                Some(mask) => masked.push_field(&self.mask_value(mask, field)?),
+                None => masked.push_field(field),
+            }
+        }
+        masked.set_position(record.position().cloned());
+        Ok(masked)
+    }
+
+    pub fn apply(self, records: Records) -> Records {
+        Box::new(records.map(move |r| r.and_then(|r| self.mask(&r))))
+    }
+}
+
+/// Made-up data picked by the bytes of a digest. Phone numbers are in the
+/// 555-01XX range reserved for fiction.
+fn fake_value(kind: FakeKind, digest: &[u8]) -> String {
+    let first = FIRST_NAMES[digest[0] as usize % FIRST_NAMES.len()];
+    let last = LAST_NAMES[digest[1] as usize % LAST_NAMES.len()];
+    match kind {
+        FakeKind::Name => format!("{} {}", first, last),
+        FakeKind::Email => format!(
+            "{}.{}{}@example.com",
+            first.to_lowercase(),
+            last.to_lowercase(),
+            u16::from_le_bytes([digest[2], digest[3]]) % 1000
+        ),
+        FakeKind::Phone => format!("555-01{:02}", digest[4] % 100),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(column: &str, mask: Mask) -> MaskRule {
+        MaskRule {
+            column: column.to_string(),
+            mask,
+        }
+    }
+
+    #[test]
+    fn test_mask_values() -> Result<()> {
+        let headers = StringRecord::from(vec!["name", "email", "notes", "id"]);
+        let rules = [
+            rule("name", Mask::Fake(FakeKind::Name)),
+            rule("email", Mask::Hash),
+            rule("notes", Mask::Truncate(3)),
+        ];
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("blake3.txt");
+        std::fs::write(&key, [7u8; 32])?;
+        let masker = Masker::new(&headers, &rules, key.to_str())?;
+        let a = masker.mask(&StringRecord::from(vec![
+            "Ann Lee",
+            "ann@x.org",
+            "héllo",
+            "1",
+        ]))?;
+        let b = masker.mask(&StringRecord::from(vec!["Ann Lee", "ann@x.org", "", "2"]))?;
+        assert_eq!(&a[2], "hél");
+        assert_eq!(&b[2], "");
+        assert_eq!(&a[3], "1");
+        // same value, same replacement
+        assert_eq!((&a[0], &a[1]), (&b[0], &b[1]));
+        assert_eq!(a[1].len(), 32);
+        assert_ne!(&a[1], blake3::hash(b"ann@x.org").to_hex()[..32].to_string());
+        assert!(a[0].split(' ').count() == 2 && a[0] != *"Ann Lee");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_needs_a_key() {
+        let headers = StringRecord::from(vec!["email"]);
+        assert!(Masker::new(&headers, &[rule("email", Mask::Hash)], None).is_err());
+        assert!(Masker::new(&headers, &[rule("nope", Mask::Redact("x".into()))], None).is_err());
+        let masker = Masker::new(
+            &headers,
+            &[rule("email", Mask::Fake(FakeKind::Email))],
+            None,
+        )
+        .unwrap();
+        let masked = masker.mask(&StringRecord::from(vec!["ann@x.org"])).unwrap();
+        assert!(masked[0].ends_with("@example.com"));
+        // keyed even without --mask-key, so guessed values cannot be checked
+        assert_ne!(
+            masker.digest("ann@x.org").unwrap(),
+            blake3::hash(b"ann@x.org").as_bytes()
+        );
+        let again = Masker::new(
+            &headers,
+            &[rule("email", Mask::Fake(FakeKind::Email))],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            again.mask(&StringRecord::from(vec!["ann@x.org"])).unwrap()[0],
+            masked[0]
+        );
+    }
+}
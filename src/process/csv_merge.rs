@@ -0,0 +1,98 @@
+use crate::cli::{CsvOpts, MergeOpts};
+use crate::process::csv_convert::convert_records;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_source::{Input, Records};
+use anyhow::{Result, bail};
+use csv::StringRecord;
+
+/// `rcli csv merge`: the records of every file in turn, under the union of
+/// their headers. Columns are matched by name and missing ones left empty.
+pub fn process_csv_merge(output: &str, opts: &CsvOpts, merge: &MergeOpts) -> Result<()> {
+    if merge.files.iter().filter(|f| *f == "-").count() > 1 {
+        bail!("Stdin can only be merged once");
+    }
+    let rereads = merge.conversion.infers_types() || merge.conversion.records.keep_last();
+    let inputs = merge
+        .files
+        .iter()
+        .map(|f| Input::new(f, rereads))
+        .collect::<Result<Vec<_>>>()?;
+    let rejects = Rejects::new(opts);
+    convert_records(
+        |r| merged_records(&inputs, opts, r),
+        output,
+        &merge.conversion,
+        &rejects,
+    )?;
+    rejects.finish()
+}
+
+fn merged_records(
+    inputs: &[Input],
+    opts: &CsvOpts,
+    rejects: &Rejects,
+) -> Result<(StringRecord, Records)> {
+    let mut headers = Vec::new();
+    let mut sources = Vec::new();
+    for input in inputs {
+        let (file_headers, records) = input.records(opts, rejects)?;
+        sources.push((align(&mut headers, &file_headers), records));
+    }
+    let width = headers.len();
+    let records = sources.into_iter().flat_map(move |(positions, records)| {
+        records.map(move |r| {
+            r.map(|r| {
+                let mut fields = vec![""; width];
+                for (field, &i) in r.iter().zip(&positions) {
+                    fields[i] = field;
+                }
+                let mut aligned = StringRecord::from(fields);
+                aligned.set_position(r.position().cloned());
+                aligned
+            })
+        })
+    });
+    Ok((StringRecord::from(headers), Box::new(records)))
+}
+
+/// Where each of `file_headers` goes in the merged `headers`, adding the
+/// names not seen before. A name repeated within a file takes the next
+/// column of that name.
+fn align(headers: &mut Vec<String>, file_headers: &StringRecord) -> Vec<usize> {
+    let mut positions: Vec<usize> = Vec::with_capacity(file_headers.len());
+    for name in file_headers {
+        let existing = (0..headers.len()).find(|&i| headers[i] == name && !positions.contains(&i));
+        let position = existing.unwrap_or_else(|| {
+            headers.push(name.to_string());
+            headers.len() - 1
+        });
+        positions.push(position);
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_headers() {
+        let mut headers = Vec::new();
+        assert_eq!(
+            align(&mut headers, &StringRecord::from(vec!["id", "title"])),
+            [0, 1]
+        );
+        assert_eq!(
+            align(
+                &mut headers,
+                &StringRecord::from(vec!["date", "id", "x", "x"])
+            ),
+            [2, 0, 3, 4]
+        );
+        assert_eq!(
+            align(&mut headers, &StringRecord::from(vec!["x", "title"])),
+            [3, 1]
+        );
+        assert_eq!(headers, ["id", "title", "date", "x", "x"]);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Result, bail};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Split a header such as `address.city` or `tags[0]` into path segments.
+/// Headers that are not a well-formed path are kept as a single key.
+pub fn header_path(header: &str) -> Vec<PathSegment> {
+    parse_path(header).unwrap_or_else(|| vec![PathSegment::Key(header.to_string())])
+}
+
+fn parse_path(header: &str) -> Option<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    for part in header.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(i) => part.split_at(i),
+            None => (part, ""),
+        };
+        if key.is_empty() && (segments.is_empty() || rest.is_empty()) {
+            return None;
+        }
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.to_string()));
+        }
+        while !rest.is_empty() {
+            let end = rest.find(']')?;
+            let index = rest[1..end].parse().ok()?;
+            segments.push(PathSegment::Index(index));
+            rest = &rest[end + 1..];
+            if !rest.is_empty() && !rest.starts_with('[') {
+                return None;
+            }
+        }
+    }
+    Some(segments)
+}
+
+/// Build a nested object from `(path, value)` pairs. Arrays are padded with
+/// `null` when indices are skipped.
+pub fn nest_record<'a, I>(fields: I) -> Result<Value>
+where
+    I: IntoIterator<Item = (&'a [PathSegment], Value)>,
+{
+    let mut root = Value::Object(Map::new());
+    for (path, value) in fields {
+        insert(&mut root, path, value)?;
+    }
+    Ok(root)
+}
+
+fn insert(node: &mut Value, path: &[PathSegment], value: Value) -> Result<()> {
+    let Some((first, rest)) = path.split_first() else {
+        if !node.is_null() {
+            bail!("Conflicting columns for the same nested field");
+        }
+        *node = value;
+        return Ok(());
+    };
+    let empty = match rest.first() {
+        Some(PathSegment::Index(_)) => Value::Array(Vec::new()),
+        Some(PathSegment::Key(_)) => Value::Object(Map::new()),
+        None => Value::Null,
+    };
+    let child = match (first, node) {
+        (PathSegment::Key(key), Value::Object(map)) => map.entry(key.clone()).or_insert(empty),
+        (PathSegment::Index(i), Value::Array(items)) => {
+            if items.len() <= *i {
+                items.resize(*i + 1, Value::Null);
+            }
+            if items[*i].is_null() {
+                items[*i] = empty;
+            }
+            &mut items[*i]
+        }
+        _ => bail!("Column path {:?} conflicts with another column", path),
+    };
+    insert(child, rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PathSegment::{Index, Key};
+    use serde_json::json;
+
+    #[test]
+    fn test_header_path() {
+        assert_eq!(
+            header_path("address.city"),
+            vec![Key("address".into()), Key("city".into())]
+        );
+        assert_eq!(header_path("tags[1]"), vec![Key("tags".into()), Index(1)]);
+        assert_eq!(
+            header_path("a[0][2].b"),
+            vec![Key("a".into()), Index(0), Index(2), Key("b".into())]
+        );
+        assert_eq!(
+            header_path("Accession No"),
+            vec![Key("Accession No".into())]
+        );
+        assert_eq!(header_path("a..b"), vec![Key("a..b".into())]);
+        assert_eq!(header_path("a[x]"), vec![Key("a[x]".into())]);
+    }
+
+    #[test]
+    fn test_nest_record() -> Result<()> {
+        let paths: Vec<_> = ["id", "address.city", "address.zip", "tags[1]", "tags[0]"]
+            .iter()
+            .map(|h| header_path(h))
+            .collect();
+        let values = [
+            json!(1),
+            json!("Oslo"),
+            json!("0150"),
+            json!("b"),
+            json!("a"),
+        ];
+        let nested = nest_record(paths.iter().map(Vec::as_slice).zip(values))?;
+        assert_eq!(
+            nested,
+            json!({"id": 1, "address": {"city": "Oslo", "zip": "0150"}, "tags": ["a", "b"]})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_columns() {
+        let paths = [header_path("a"), header_path("a.b")];
+        let values = [json!(1), json!(2)];
+        assert!(nest_record(paths.iter().map(Vec::as_slice).zip(values)).is_err());
+    }
+}
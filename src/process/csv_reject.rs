@@ -0,0 +1,185 @@
+use crate::cli::{CsvOpts, OnError};
+use anyhow::{Context, Result};
+use csv::{StringRecord, Writer, WriterBuilder};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::rc::Rc;
+
+/// A record that could not be read or converted, kept whole so it can be
+/// written to the reject file.
+#[derive(Debug)]
+pub struct MalformedRecord {
+    pub line: u64,
+    pub fields: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for MalformedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for MalformedRecord {}
+
+impl MalformedRecord {
+    pub fn new(record: &StringRecord, line: u64, reason: String) -> Self {
+        Self {
+            line,
+            fields: record.iter().map(String::from).collect(),
+            reason,
+        }
+    }
+}
+
+/// Applies `--on-error` to malformed records. Clones share the counts and
+/// the reject file, so stages of one pass, and several inputs, can report
+/// into the same place.
+#[derive(Clone)]
+pub struct Rejects {
+    policy: OnError,
+    silent: bool,
+    source: String,
+    inner: Rc<RefCell<RejectLog>>,
+}
+
+struct RejectLog {
+    path: Option<String>,
+    writer: Option<Writer<File>>,
+    count: usize,
+}
+
+impl Rejects {
+    pub fn new(opts: &CsvOpts) -> Self {
+        let path = (opts.on_error == OnError::Reject).then(|| opts.reject_file.clone());
+        Self::with_path(opts.on_error, path, false)
+    }
+
+    /// Same policy, but dropped records are not written or counted; for the
+    /// extra passes over the input that come before the real one.
+    pub fn silent(&self) -> Self {
+        Self::with_path(self.policy, None, true).for_source(&self.source)
+    }
+
+    /// The same log, with the records it is handed marked as coming from
+    /// `source`.
+    pub fn for_source(&self, source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            ..self.clone()
+        }
+    }
+
+    fn with_path(policy: OnError, path: Option<String>, silent: bool) -> Self {
+        let log = RejectLog {
+            path,
+            writer: None,
+            count: 0,
+        };
+        Self {
+            policy,
+            silent,
+            source: String::new(),
+            inner: Rc::new(RefCell::new(log)),
+        }
+    }
+
+    /// Drop the record behind `error`, or hand the error back when the policy
+    /// is `fail` or the error is not about a single record (e.g. I/O).
+    pub fn handle(&self, error: anyhow::Error) -> Result<()> {
+        let Some(record) = error.downcast_ref::<MalformedRecord>() else {
+            return Err(error);
+        };
+        if self.policy == OnError::Fail {
+            return Err(error);
+        }
+        if self.silent {
+            return Ok(());
+        }
+        let mut log = self.inner.borrow_mut();
+        log.count += 1;
+        let Some(path) = log.path.clone() else {
+            return Ok(());
+        };
+        if log.writer.is_none() {
+            let writer = WriterBuilder::new()
+                .flexible(true)
+                .from_path(&path)
+                .with_context(|| format!("creating {}", path))?;
+            log.writer = Some(writer);
+        }
+        if let Some(writer) = &mut log.writer {
+            let line = record.line.to_string();
+            let prefix = [self.source.as_str(), line.as_str(), record.reason.as_str()];
+            writer.write_record(
+                prefix
+                    .into_iter()
+                    .chain(record.fields.iter().map(String::as_str)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flush the reject file and say how many records were left out.
+    pub fn finish(&self) -> Result<()> {
+        let mut log = self.inner.borrow_mut();
+        if let Some(writer) = &mut log.writer {
+            writer.flush()?;
+        }
+        match (&log.path, log.count) {
+            (_, 0) => {}
+            (Some(path), n) => eprintln!("{} malformed records rejected to {}", n, path),
+            (None, n) => eprintln!("{} malformed records skipped", n),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+    use anyhow::anyhow;
+
+    fn rejects(args: &[&str]) -> Rejects {
+        Rejects::new(&test_opts(args))
+    }
+
+    fn malformed(line: u64) -> anyhow::Error {
+        let record = StringRecord::from(vec!["a", "b", "c"]);
+        MalformedRecord::new(&record, line, "found 3 fields, expected 2".to_string()).into()
+    }
+
+    #[test]
+    fn test_fail_and_skip() {
+        assert!(rejects(&[]).handle(malformed(3)).is_err());
+        let skip = rejects(&["--on-error", "skip"]);
+        assert!(skip.handle(malformed(3)).is_ok());
+        assert!(skip.handle(anyhow!("disk full")).is_err());
+        assert_eq!(skip.inner.borrow().count, 1);
+        let silent = skip.silent();
+        assert!(silent.handle(malformed(4)).is_ok());
+        assert_eq!(
+            (skip.inner.borrow().count, silent.inner.borrow().count),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn test_reject_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rejected.csv");
+        let path = path.to_str().unwrap();
+        let reject = rejects(&["--on-error", "reject", "--reject-file", path]);
+        reject.for_source("x.csv").handle(malformed(3))?;
+        reject.for_source("y.csv").handle(malformed(7))?;
+        reject.finish()?;
+        assert_eq!(reject.inner.borrow().count, 2);
+        assert_eq!(
+            std::fs::read_to_string(path)?,
+            "x.csv,3,\"found 3 fields, expected 2\",a,b,c\ny.csv,7,\"found 3 fields, expected 2\",a,b,c\n"
+        );
+        Ok(())
+    }
+}
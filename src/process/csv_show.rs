@@ -0,0 +1,258 @@
+use crate::cli::WriterOpts;
+use crate::process::csv_writer::{RecordWriter, cell_text};
+use anyhow::Result;
+use serde_json::Value;
+use std::io::{self, ErrorKind, Write};
+use std::process::{Child, Command, Stdio};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Renders records as an aligned box-drawing table. Rows are buffered one page
+/// at a time and every page is laid out on its own, so large inputs are shown
+/// without holding them in memory.
+pub struct TableWriter<W: Write> {
+    writer: W,
+    max_width: usize,
+    wrap: bool,
+    page_size: usize,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+/// Pipes output through `$PAGER` (`less` by default).
+pub struct Pager {
+    child: Child,
+}
+
+impl<W: Write> RecordWriter for TableWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("Table output expects records to be objects");
+        };
+        if self.headers.is_empty() {
+            self.headers = map.keys().cloned().collect();
+        }
+        let row = self
+            .headers
+            .iter()
+            .map(|h| map.get(h).map(cell_text).unwrap_or_default())
+            .collect();
+        self.rows.push(row);
+        if self.rows.len() >= self.page_size {
+            self.flush_page()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.rows.is_empty() {
+            self.flush_page()?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> TableWriter<W> {
+    pub fn new(writer: W, opts: &WriterOpts) -> Self {
+        Self {
+            writer,
+            max_width: opts.max_width.max(1),
+            wrap: opts.wrap,
+            page_size: opts.page_size.max(1),
+            headers: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    fn flush_page(&mut self) -> Result<()> {
+        let page = render_table(&self.headers, &self.rows, self.max_width, self.wrap);
+        self.writer.write_all(page.as_bytes())?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+pub fn render_table(
+    headers: &[String],
+    rows: &[Vec<String>],
+    max_width: usize,
+    wrap: bool,
+) -> String {
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|i| {
+            std::iter::once(&headers[i])
+                .chain(rows.iter().map(|r| &r[i]))
+                .flat_map(|cell| cell.lines())
+                .map(UnicodeWidthStr::width)
+                .max()
+                .unwrap_or(0)
+                .clamp(1, max_width)
+        })
+        .collect();
+
+    let mut out = String::new();
+    out.push_str(&border(&widths, '┌', '┬', '┐'));
+    out.push_str(&render_row(headers, &widths, wrap));
+    out.push_str(&border(&widths, '├', '┼', '┤'));
+    for row in rows {
+        out.push_str(&render_row(row, &widths, wrap));
+    }
+    out.push_str(&border(&widths, '└', '┴', '┘'));
+    out
+}
+
+fn border(widths: &[usize], left: char, mid: char, right: char) -> String {
+    let segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+    format!("{}{}{}\n", left, segments.join(&mid.to_string()), right)
+}
+
+fn render_row(cells: &[String], widths: &[usize], wrap: bool) -> String {
+    let cells: Vec<Vec<String>> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, &width)| {
+            if wrap {
+                wrap_text(cell, width)
+            } else {
+                vec![truncate(&cell.replace(['\r', '\n'], " "), width)]
+            }
+        })
+        .collect();
+    let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+    let mut out = String::new();
+    for line in 0..height {
+        out.push('│');
+        for (cell, &width) in cells.iter().zip(widths) {
+            let text = cell.get(line).map(String::as_str).unwrap_or("");
+            out.push(' ');
+            out.push_str(text);
+            out.push_str(&" ".repeat(width.saturating_sub(text.width())));
+            out.push_str(" │");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Cut `text` to at most `width` columns, marking the cut with `…`.
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width - 1 {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    out.push('…');
+    out
+}
+
+/// Greedy word wrap; words wider than `width` are split.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for source in text.lines() {
+        let mut line = String::new();
+        for word in source.split(' ') {
+            let sep = usize::from(!line.is_empty());
+            if line.width() + sep + word.width() <= width {
+                if sep == 1 {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            for c in word.chars() {
+                if !line.is_empty() && line.width() + c.width().unwrap_or(0) > width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(c);
+            }
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
+
+impl Pager {
+    pub fn spawn() -> Result<Self> {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -FRSX".to_string());
+        let mut parts = pager.split_whitespace();
+        let program = parts.next().unwrap_or("less");
+        let child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        Ok(Self { child })
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stdin = self.child.stdin.as_mut().expect("pager stdin is piped");
+        match stdin.write(buf) {
+            // the user quit the pager early; drop the rest quietly
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let stdin = self.child.stdin.as_mut().expect("pager stdin is piped");
+        match stdin.flush() {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        drop(self.child.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let headers = vec!["name".to_string(), "note".to_string()];
+        let rows = vec![vec!["Zoë".to_string(), "a long note".to_string()]];
+        let table = render_table(&headers, &rows, 6, false);
+        assert_eq!(
+            table,
+            "┌──────┬────────┐\n\
+             │ name │ note   │\n\
+             ├──────┼────────┤\n\
+             │ Zoë  │ a lon… │\n\
+             └──────┴────────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        assert_eq!(wrap_text("a long note", 6), vec!["a long", "note"]);
+        assert_eq!(wrap_text("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap_text("", 3), vec![""]);
+    }
+
+    #[test]
+    fn test_truncate_wide_chars() {
+        assert_eq!(truncate("日本語テキスト", 5), "日本…");
+        assert_eq!(truncate("short", 5), "short");
+    }
+}
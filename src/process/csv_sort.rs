@@ -0,0 +1,423 @@
+use crate::cli::{SortKey, SortOrder};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_source::Records;
+use anyhow::{Context, Result};
+use csv::{Position, ReaderBuilder, StringRecord, StringRecordsIntoIter, WriterBuilder};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::rc::Rc;
+use tempfile::{NamedTempFile, TempPath};
+
+/// `--sort-by` keys resolved against the input's headers.
+#[derive(Debug)]
+pub struct SortKeys {
+    keys: Vec<(usize, bool, SortOrder)>,
+}
+
+impl SortKeys {
+    pub fn new(headers: &StringRecord, keys: &[SortKey]) -> Result<Self> {
+        let keys = keys
+            .iter()
+            .map(|k| {
+                let idx = column_index(headers, &k.column).context("in --sort-by")?;
+                Ok((idx, k.descending, k.order))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { keys })
+    }
+
+    pub fn compare(&self, a: &StringRecord, b: &StringRecord) -> Ordering {
+        for &(idx, descending, order) in &self.keys {
+            let (a, b) = (a.get(idx).unwrap_or(""), b.get(idx).unwrap_or(""));
+            let ordering = match order {
+                SortOrder::Lexical => a.cmp(b),
+                SortOrder::Numeric => numeric_cmp(a, b),
+                SortOrder::Natural => natural_cmp(a, b),
+            };
+            let ordering = if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// Numbers in numeric order, followed by the values that are not numbers.
+pub fn numeric_cmp(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Compare runs of digits by their value, so `file2` sorts before `file10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (na, ra) = split_digits(a);
+            let (nb, rb) = split_digits(b);
+            (a, b) = (ra, rb);
+            let (na, nb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+            na.len().cmp(&nb.len()).then_with(|| na.cmp(nb))
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            x.cmp(&y)
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Runs merged at once. Runs are kept closed until they are merged, and
+/// more of them are merged in several passes, so the number of open files
+/// stays bounded however large the input.
+const MERGE_FAN_IN: usize = 64;
+
+/// Sort the records, keeping equal ones in input order. At most `buffer`
+/// records are held in memory: larger inputs are sorted in runs that are
+/// spilled to temporary files and merged back.
+pub fn sort_records(records: Records, keys: SortKeys, buffer: usize) -> Result<Records> {
+    let keys = Rc::new(keys);
+    let buffer = buffer.max(1);
+    let mut runs = Vec::new();
+    let mut chunk = Vec::new();
+    for record in records {
+        chunk.push(record?);
+        if chunk.len() == buffer {
+            runs.push(spill(&mut chunk, &keys)?);
+        }
+    }
+    if runs.is_empty() {
+        chunk.sort_by(|a, b| keys.compare(a, b));
+        return Ok(Box::new(chunk.into_iter().map(Ok)));
+    }
+    if !chunk.is_empty() {
+        runs.push(spill(&mut chunk, &keys)?);
+    }
+    // neighbouring runs are merged together, so earlier input stays first
+    while runs.len() > MERGE_FAN_IN {
+        let mut rest = runs.into_iter();
+        runs = Vec::new();
+        loop {
+            let group: Vec<_> = rest.by_ref().take(MERGE_FAN_IN).collect();
+            if group.is_empty() {
+                break;
+            }
+            runs.push(write_run(Merge::new(group, keys.clone())?)?);
+        }
+    }
+    Ok(Box::new(Merge::new(runs, keys)?))
+}
+
+type Run = StringRecordsIntoIter<BufReader<File>>;
+
+/// Sort a chunk and write it to a temporary file.
+fn spill(chunk: &mut Vec<StringRecord>, keys: &SortKeys) -> Result<TempPath> {
+    chunk.sort_by(|a, b| keys.compare(a, b));
+    write_run(chunk.drain(..).map(Ok))
+}
+
+/// Write sorted records to a temporary file. The record position goes along
+/// as two trailing fields so errors can still name the input line.
+fn write_run(records: impl Iterator<Item = Result<StringRecord>>) -> Result<TempPath> {
+    let file = NamedTempFile::new().context("creating a sort run")?;
+    let mut wtr = WriterBuilder::new()
+        .flexible(true)
+        .from_writer(BufWriter::new(file.as_file()));
+    for record in records {
+        let record = record?;
+        let position = record.position().cloned().unwrap_or_else(Position::new);
+        let mut fields = record;
+        fields.push_field(&position.line().to_string());
+        fields.push_field(&position.record().to_string());
+        wtr.write_record(&fields)?;
+    }
+    wtr.flush()?;
+    drop(wtr);
+    Ok(file.into_temp_path())
+}
+
+fn open_run(path: &TempPath) -> Result<Run> {
+    let file = File::open(path).context("reading a sort run")?;
+    let rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(file));
+    Ok(rdr.into_records())
+}
+
+fn read_run(run: &mut Run) -> Result<Option<StringRecord>> {
+    let Some(fields) = run.next().transpose()? else {
+        return Ok(None);
+    };
+    let n = fields.len().saturating_sub(2);
+    let mut record: StringRecord = fields.iter().take(n).collect();
+    let mut position = Position::new();
+    position
+        .set_line(fields.get(n).and_then(|l| l.parse().ok()).unwrap_or(0))
+        .set_record(fields.get(n + 1).and_then(|r| r.parse().ok()).unwrap_or(0));
+    record.set_position(Some(position));
+    Ok(Some(record))
+}
+
+/// The next record of a run, ordered so the heap pops the smallest one
+/// first, and the earlier run among equals.
+struct Head {
+    record: StringRecord,
+    run: usize,
+    keys: Rc<SortKeys>,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.keys
+            .compare(&self.record, &other.record)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head {}
+
+/// k-way merge of sorted runs; the files are removed once it is dropped.
+struct Merge {
+    runs: Vec<Run>,
+    heads: BinaryHeap<Head>,
+    keys: Rc<SortKeys>,
+    _paths: Vec<TempPath>,
+}
+
+impl Merge {
+    fn new(paths: Vec<TempPath>, keys: Rc<SortKeys>) -> Result<Self> {
+        let mut runs = paths.iter().map(open_run).collect::<Result<Vec<_>>>()?;
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        for (run, records) in runs.iter_mut().enumerate() {
+            if let Some(record) = read_run(records)? {
+                heads.push(Head {
+                    record,
+                    run,
+                    keys: keys.clone(),
+                });
+            }
+        }
+        Ok(Self {
+            runs,
+            heads,
+            keys,
+            _paths: paths,
+        })
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        match read_run(&mut self.runs[head.run]) {
+            Ok(Some(record)) => self.heads.push(Head {
+                record,
+                run: head.run,
+                keys: self.keys.clone(),
+            }),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(head.record))
+    }
+}
+
+/// `--dedup`: recognizes records that repeat the values of the key columns.
+#[derive(Debug)]
+pub struct Dedup {
+    indices: Vec<usize>,
+}
+
+impl Dedup {
+    /// An empty `columns` makes the whole record the key.
+    pub fn new(headers: &StringRecord, columns: &[String]) -> Result<Self> {
+        let indices = if columns.is_empty() {
+            (0..headers.len()).collect()
+        } else {
+            columns
+                .iter()
+                .map(|c| column_index(headers, c).context("in --dedup"))
+                .collect::<Result<_>>()?
+        };
+        Ok(Self { indices })
+    }
+
+    /// Only the key's hash is remembered, so memory grows with the number of
+    /// distinct keys rather than with their size.
+    fn key(&self, record: &StringRecord) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        for &i in &self.indices {
+            let field = record.get(i).unwrap_or("");
+            hasher.update(&(field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.finalize()
+    }
+
+    pub fn keep_first(self, records: Records) -> Records {
+        let mut seen = HashSet::new();
+        Box::new(records.filter(move |r| r.as_ref().map_or(true, |r| seen.insert(self.key(r)))))
+    }
+
+    /// Where each key is seen last, counted in records of the stream.
+    pub fn last_occurrences(&self, records: Records) -> Result<HashMap<blake3::Hash, usize>> {
+        let mut last = HashMap::new();
+        for (i, record) in records.enumerate() {
+            last.insert(self.key(&record?), i);
+        }
+        Ok(last)
+    }
+
+    /// Keep the records found by `last_occurrences` on the same stream.
+    pub fn keep_last(self, records: Records, last: HashMap<blake3::Hash, usize>) -> Records {
+        Box::new(records.enumerate().filter_map(move |(i, r)| match r {
+            Ok(r) if last.get(&self.key(&r)) != Some(&i) => None,
+            r => Some(r),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(rows: &[&[&str]]) -> Records {
+        let rows: Vec<StringRecord> = rows
+            .iter()
+            .map(|r| StringRecord::from(r.to_vec()))
+            .collect();
+        Box::new(rows.into_iter().map(Ok))
+    }
+
+    fn keys(spec: &[(&str, bool, SortOrder)]) -> SortKeys {
+        let headers = StringRecord::from(vec!["name", "n"]);
+        let keys: Vec<SortKey> = spec
+            .iter()
+            .map(|&(column, descending, order)| SortKey {
+                column: column.to_string(),
+                descending,
+                order,
+            })
+            .collect();
+        SortKeys::new(&headers, &keys).unwrap()
+    }
+
+    fn column(records: Records, idx: usize) -> Vec<String> {
+        records.map(|r| r.unwrap()[idx].to_string()).collect()
+    }
+
+    #[test]
+    fn test_natural_and_numeric_order() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file010", "file10"), Ordering::Equal);
+        assert_eq!(natural_cmp("a1b", "a1"), Ordering::Greater);
+        assert_eq!(numeric_cmp("9", "10.5"), Ordering::Less);
+        assert_eq!(numeric_cmp("n/a", "-3"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_sort_spills_and_stays_stable() -> Result<()> {
+        let rows: &[&[&str]] = &[
+            &["e", "2"],
+            &["a", "10"],
+            &["d", "2"],
+            &["b", "1"],
+            &["c", "10"],
+        ];
+        for buffer in [100, 2, 1] {
+            let sorted = sort_records(
+                records(rows),
+                keys(&[("n", true, SortOrder::Numeric)]),
+                buffer,
+            )?;
+            assert_eq!(column(sorted, 0), ["a", "c", "e", "d", "b"]);
+        }
+        let sorted = sort_records(
+            records(rows),
+            keys(&[("name", false, SortOrder::Lexical)]),
+            2,
+        )?;
+        assert_eq!(column(sorted, 0), ["a", "b", "c", "d", "e"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_merges_many_runs_in_passes() -> Result<()> {
+        let names: Vec<String> = (0..300).map(|i| format!("r{}", i)).collect();
+        let rows: Vec<Vec<&str>> = names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| vec![n.as_str(), ["2", "1"][i % 2]])
+            .collect();
+        let rows: Vec<&[&str]> = rows.iter().map(Vec::as_slice).collect();
+        let sorted = column(
+            sort_records(records(&rows), keys(&[("n", false, SortOrder::Numeric)]), 1)?,
+            0,
+        );
+        let odd = names.iter().skip(1).step_by(2);
+        let even = names.iter().step_by(2);
+        assert_eq!(sorted, odd.chain(even).cloned().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_keeps_first_or_last() -> Result<()> {
+        let rows: &[&[&str]] = &[
+            &["a", "1"],
+            &["b", "2"],
+            &["a", "3"],
+            &["c", "4"],
+            &["b", "5"],
+        ];
+        let headers = StringRecord::from(vec!["name", "n"]);
+        let dedup = Dedup::new(&headers, &["name".to_string()])?;
+        assert_eq!(column(dedup.keep_first(records(rows)), 1), ["1", "2", "4"]);
+
+        let dedup = Dedup::new(&headers, &["name".to_string()])?;
+        let last = dedup.last_occurrences(records(rows))?;
+        assert_eq!(
+            column(dedup.keep_last(records(rows), last), 1),
+            ["3", "4", "5"]
+        );
+
+        let dedup = Dedup::new(&headers, &[])?;
+        assert_eq!(column(dedup.keep_first(records(rows)), 1).len(), 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use crate::cli::{CsvOpts, InputFormat};
+use crate::process::csv_convert::read_headers;
+use crate::process::csv_reject::{MalformedRecord, Rejects};
+use crate::process::csv_writer::cell_text;
+use crate::utils::{get_reader, get_rewindable_reader};
+use anyhow::{Result, bail};
+use csv::{ByteRecord, Position, StringRecord};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+pub type Records = Box<dyn Iterator<Item = Result<StringRecord>>>;
+
+/// The input of a conversion, opened once for every pass over it. Stdin is
+/// spooled to a temporary file when it has to be read more than once.
+pub struct Input {
+    path: String,
+    spooled: Option<File>,
+}
+
+impl Input {
+    pub fn new(path: &str, rereadable: bool) -> Result<Self> {
+        let spooled = if rereadable && path == "-" {
+            Some(get_rewindable_reader(path)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            path: path.to_string(),
+            spooled,
+        })
+    }
+
+    /// A reader from the start of the input. Readers of a spooled stdin share
+    /// one cursor, so a pass must be finished before the next one is opened.
+    pub fn reader(&self) -> Result<Box<dyn Read>> {
+        match &self.spooled {
+            Some(file) => {
+                let mut file = file.try_clone()?;
+                file.rewind()?;
+                Ok(Box::new(file))
+            }
+            None => get_reader(&self.path),
+        }
+    }
+
+    /// Open the records of one pass, with malformed ones handed to `rejects`.
+    pub fn records(&self, opts: &CsvOpts, rejects: &Rejects) -> Result<(StringRecord, Records)> {
+        let format = opts.input_format(&self.path);
+        let (headers, records) = open_records(self.reader()?, format, opts)?;
+        let rejects = rejects.for_source(&self.path);
+        let records = records.filter_map(move |r| match r {
+            Ok(r) => Some(Ok(r)),
+            Err(e) => rejects.handle(e).err().map(Err),
+        });
+        Ok((headers, Box::new(records)))
+    }
+}
+
+/// Open `reader` as a stream of CSV-shaped records. JSON, YAML and NDJSON
+/// documents are flattened into columns; they are held in memory because the
+/// header is the union of the keys of every object.
+pub fn open_records(
+    reader: Box<dyn Read>,
+    format: InputFormat,
+    opts: &CsvOpts,
+) -> Result<(StringRecord, Records)> {
+    let reader = decoded(reader, opts.encoding)?;
+    let docs = match format {
+        InputFormat::Csv => {
+            // record lengths are checked here, so ragged rows can be rejected
+            let dialect = &opts.dialect;
+            let mut rdr = dialect.reader_builder().flexible(true).from_reader(reader);
+            let headers = read_headers(&mut rdr, dialect)?;
+            let width = (!dialect.flexible).then_some(rdr.byte_headers()?.len());
+            let records = rdr
+                .into_byte_records()
+                .map(move |r| string_record(r?, width));
+            return Ok((headers, Box::new(records)));
+        }
+        InputFormat::Json => match serde_json::from_reader(reader)? {
+            Value::Array(docs) => docs,
+            doc => vec![doc],
+        },
+        InputFormat::Yaml => match serde_yaml::from_reader(reader)? {
+            Value::Array(docs) => docs,
+            doc => vec![doc],
+        },
+        InputFormat::Jsonl => {
+            let mut docs = Vec::new();
+            for line in BufReader::new(reader).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    docs.push(serde_json::from_str(&line)?);
+                }
+            }
+            docs
+        }
+    };
+    let (headers, rows) = flatten_records(&docs, &opts.join_sep)?;
+    Ok((headers, Box::new(rows.into_iter().map(Ok))))
+}
+
+/// How much of the input is looked at to guess its encoding.
+const SNIFF_LEN: u64 = 64 * 1024;
+
+/// Transcode the input to UTF-8, dropping any byte order mark. Without an
+/// explicit `encoding`, a BOM decides; otherwise the start of the input is
+/// checked for UTF-16 and for bytes that are not valid UTF-8, which are read
+/// as Windows-1252 like most Excel exports.
+fn decoded(
+    mut reader: Box<dyn Read>,
+    encoding: Option<&'static Encoding>,
+) -> Result<Box<dyn Read>> {
+    let mut builder = DecodeReaderBytesBuilder::new();
+    if let Some(encoding) = encoding {
+        builder.encoding(Some(encoding));
+        return Ok(Box::new(builder.build(reader)));
+    }
+    let mut sample = Vec::new();
+    reader.by_ref().take(SNIFF_LEN).read_to_end(&mut sample)?;
+    builder
+        .encoding(detect_encoding(&sample))
+        .bom_override(true);
+    Ok(Box::new(builder.build(Cursor::new(sample).chain(reader))))
+}
+
+/// `None` when the sample starts with a BOM or is UTF-8.
+fn detect_encoding(sample: &[u8]) -> Option<&'static Encoding> {
+    if Encoding::for_bom(sample).is_some() {
+        return None;
+    }
+    let zeros = |parity: usize| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    if sample.len() >= 4 && even == 0 && odd * 4 >= sample.len() {
+        return Some(UTF_16LE);
+    }
+    if sample.len() >= 4 && odd == 0 && even * 4 >= sample.len() {
+        return Some(UTF_16BE);
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => None,
+        // the sample may end in the middle of a character
+        Err(e) if e.error_len().is_none() => None,
+        Err(_) => Some(WINDOWS_1252),
+    }
+}
+
+fn string_record(record: ByteRecord, width: Option<usize>) -> Result<StringRecord> {
+    let line = record.position().map_or(0, |p| p.line());
+    let malformed = |record: &ByteRecord, reason| {
+        let lossy: StringRecord = record.iter().map(|f| String::from_utf8_lossy(f)).collect();
+        MalformedRecord::new(&lossy, line, reason)
+    };
+    if let Some(width) = width
+        && record.len() != width
+    {
+        let reason = format!("found {} fields, expected {}", record.len(), width);
+        return Err(malformed(&record, reason).into());
+    }
+    StringRecord::from_byte_record(record).map_err(|e| {
+        let reason = format!("invalid UTF-8 in field {}", e.utf8_error().field() + 1);
+        malformed(&e.into_byte_record(), reason).into()
+    })
+}
+
+/// Flatten objects into rows under the union of their (dotted) keys, in the
+/// order the keys are first seen.
+pub fn flatten_records(docs: &[Value], sep: &str) -> Result<(StringRecord, Vec<StringRecord>)> {
+    let mut headers: Vec<String> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut flat_docs = Vec::with_capacity(docs.len());
+    for (i, doc) in docs.iter().enumerate() {
+        if !doc.is_object() {
+            bail!("Expected an object at record {}, got: {}", i + 1, doc);
+        }
+        let mut fields = Vec::new();
+        flatten_value("", doc, sep, &mut fields);
+        for (key, _) in &fields {
+            if !index.contains_key(key) {
+                index.insert(key.clone(), headers.len());
+                headers.push(key.clone());
+            }
+        }
+        flat_docs.push(fields);
+    }
+    let rows = flat_docs
+        .into_iter()
+        .enumerate()
+        .map(|(i, fields)| {
+            let mut row = vec![String::new(); headers.len()];
+            for (key, value) in fields {
+                row[index[&key]] = value;
+            }
+            let mut record = StringRecord::from(row);
+            let mut position = Position::new();
+            position.set_line(i as u64 + 1).set_record(i as u64);
+            record.set_position(Some(position));
+            record
+        })
+        .collect();
+    Ok((StringRecord::from(headers), rows))
+}
+
+fn flatten_value(prefix: &str, value: &Value, sep: &str, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_value(&path, child, sep, out);
+            }
+        }
+        Value::Array(items) if items.iter().all(|v| !v.is_object() && !v.is_array()) => {
+            let items: Vec<String> = items.iter().map(cell_text).collect();
+            out.push((prefix.to_string(), items.join(sep)));
+        }
+        v => out.push((prefix.to_string(), cell_text(v))),
+    }
+}
+
+impl CsvOpts {
+    /// `--from`, or a guess from the extension of `path`.
+    pub fn input_format(&self, path: &str) -> InputFormat {
+        if let Some(format) = self.from {
+            return format;
+        }
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("json") => InputFormat::Json,
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            Some("yaml" | "yml") => InputFormat::Yaml,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+        let mut text = String::new();
+        decoded(Box::new(Cursor::new(bytes.to_vec())), encoding)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_detect_encodings() {
+        assert_eq!(decode(b"\xEF\xBB\xBFTitle,Date\n", None), "Title,Date\n");
+        assert_eq!(decode(b"\xFF\xFEa\x00,\x00\xE9\x00", None), "a,é");
+        assert_eq!(decode(b"a\x00,\x00\xE9\x00", None), "a,é");
+        assert_eq!(decode(b"\x00a\x00,\x00\xE9", None), "a,é");
+        assert_eq!(decode(b"caf\xE9,\x80\n", None), "café,€\n");
+        assert_eq!(decode("café".as_bytes(), None), "café");
+    }
+
+    #[test]
+    fn test_explicit_encoding() {
+        let latin1 = Encoding::for_label(b"latin1");
+        assert_eq!(decode(b"caf\xE9", latin1), "café");
+        assert_eq!(decode(b"\xEF\xBB\xBFa", Some(encoding_rs::UTF_8)), "a");
+    }
+
+    #[test]
+    fn test_flatten_records() -> Result<()> {
+        let docs = vec![
+            json!({"id": 1, "address": {"city": "Oslo", "zip": "0150"}, "tags": ["a", "b"]}),
+            json!({"id": 2, "note": null, "address": {"city": "Bergen"}}),
+        ];
+        let (headers, rows) = flatten_records(&docs, "; ")?;
+        assert_eq!(
+            headers,
+            vec!["id", "address.city", "address.zip", "tags", "note"]
+        );
+        assert_eq!(rows[0], vec!["1", "Oslo", "0150", "a; b", ""]);
+        assert_eq!(rows[1], vec!["2", "Bergen", "", "", ""]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_object_record_is_rejected() {
+        assert!(flatten_records(&[json!([1, 2])], ";").is_err());
+    }
+
+    #[test]
+    fn test_arrays_of_objects_stay_json() -> Result<()> {
+        let docs = vec![json!({"items": [{"n": 1}]})];
+        let (_, rows) = flatten_records(&docs, ";")?;
+        assert_eq!(rows[0], vec![r#"[{"n":1}]"#]);
+        Ok(())
+    }
+}
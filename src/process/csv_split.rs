@@ -0,0 +1,327 @@
+use crate::cli::{CsvOpts, CsvWriterOpts, SplitOpts};
+use crate::process::csv_columns::column_index;
+use crate::process::csv_convert::prepared_records;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_source::Input;
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// `rcli csv split`: write the records to numbered parts of at most
+/// `--rows` records or `--bytes` bytes, or to one part per value of `--by`.
+/// Every part starts with the header.
+pub fn process_csv_split(input: &str, opts: &CsvOpts, split: &SplitOpts) -> Result<()> {
+    let source = Input::new(input, split.records.keep_last())?;
+    let rejects = Rejects::new(opts);
+    let (headers, records) =
+        prepared_records(|r| source.records(opts, r), &split.records, &rejects)?;
+    let by = split
+        .by
+        .as_ref()
+        .map(|c| column_index(&headers, c).context("in --by"))
+        .transpose()?;
+    let prefix = match &split.prefix {
+        Some(prefix) => prefix.clone(),
+        None => Path::new(input)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .filter(|_| input != "-")
+            .unwrap_or("part")
+            .to_string(),
+    };
+    let mut parts = Parts::new(split.out_dir.clone(), prefix, &headers, &split.csv)?;
+    for record in records {
+        let record = record?;
+        let bytes = parts.encode(&record)?;
+        let part = match by {
+            Some(i) => parts.keyed(record.get(i).unwrap_or(""))?,
+            None => parts.sequential(split.rows, split.bytes, bytes.len() as u64)?,
+        };
+        part.write(&bytes)?;
+    }
+    let count = parts.finish()?;
+    rejects.finish()?;
+    eprintln!("{} parts written to {}", count, split.out_dir.display());
+    Ok(())
+}
+
+struct Part {
+    file: BufWriter<File>,
+    rows: usize,
+    bytes: u64,
+}
+
+impl Part {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file.write_all(bytes)?;
+        self.rows += 1;
+        self.bytes += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// Parts of a `--by` split kept open at once; the least recently used one
+/// is closed and later reopened for appending.
+const MAX_OPEN_PARTS: usize = 64;
+
+struct Parts {
+    dir: PathBuf,
+    prefix: String,
+    encoder: csv::WriterBuilder,
+    header: Vec<u8>,
+    current: Option<Part>,
+    /// Open `--by` parts by value, with the tick they were last written at.
+    keyed: HashMap<String, (Part, u64)>,
+    /// The file name part of every `--by` value seen, and the names taken.
+    names: HashMap<String, String>,
+    taken: HashSet<String>,
+    tick: u64,
+    count: usize,
+}
+
+impl Parts {
+    fn new(
+        dir: PathBuf,
+        prefix: String,
+        headers: &StringRecord,
+        csv: &CsvWriterOpts,
+    ) -> Result<Self> {
+        let mut parts = Self {
+            dir,
+            prefix,
+            encoder: csv.builder(),
+            header: Vec::new(),
+            current: None,
+            keyed: HashMap::new(),
+            names: HashMap::new(),
+            taken: HashSet::new(),
+            tick: 0,
+            count: 0,
+        };
+        parts.header = parts.encode(headers)?;
+        Ok(parts)
+    }
+
+    /// A record as it will appear in a part.
+    fn encode(&self, record: &StringRecord) -> Result<Vec<u8>> {
+        let mut wtr = self.encoder.from_writer(Vec::new());
+        wtr.write_record(record)?;
+        Ok(wtr.into_inner()?)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}_{}.csv", self.prefix, name))
+    }
+
+    fn create(&mut self, name: &str) -> Result<Part> {
+        let path = self.path(name);
+        let file = File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&self.header)?;
+        self.count += 1;
+        Ok(Part {
+            file,
+            rows: 0,
+            bytes: self.header.len() as u64,
+        })
+    }
+
+    /// The current numbered part, or a new one when the next record of `len`
+    /// bytes would not fit. A part always takes at least one record.
+    fn sequential(
+        &mut self,
+        rows: Option<usize>,
+        bytes: Option<u64>,
+        len: u64,
+    ) -> Result<&mut Part> {
+        let full = self.current.as_ref().is_none_or(|part| {
+            part.rows > 0
+                && (rows.is_some_and(|n| part.rows >= n)
+                    || bytes.is_some_and(|n| part.bytes + len > n))
+        });
+        if full {
+            if let Some(mut part) = self.current.take() {
+                part.file.flush()?;
+            }
+            let part = self.create(&format!("{:04}", self.count + 1))?;
+            self.current = Some(part);
+        }
+        Ok(self.current.as_mut().expect("a part was just opened"))
+    }
+
+    /// The part for a value of the `--by` column. Values whose file names
+    /// would collide, like `b/c` and `b_c`, get a numeric suffix.
+    fn keyed(&mut self, value: &str) -> Result<&mut Part> {
+        self.tick += 1;
+        if !self.keyed.contains_key(value) {
+            if self.keyed.len() == MAX_OPEN_PARTS {
+                self.close_least_recent()?;
+            }
+            let part = match self.names.get(value) {
+                Some(name) => {
+                    let path = self.path(name);
+                    let file = OpenOptions::new()
+                        .append(true)
+                        .open(&path)
+                        .with_context(|| format!("reopening {}", path.display()))?;
+                    Part {
+                        file: BufWriter::new(file),
+                        rows: 0,
+                        bytes: 0,
+                    }
+                }
+                None => {
+                    let name = self.unique_name(value);
+                    let part = self.create(&name)?;
+                    self.names.insert(value.to_string(), name);
+                    part
+                }
+            };
+            self.keyed.insert(value.to_string(), (part, 0));
+        }
+        let (part, last_used) = self.keyed.get_mut(value).expect("the part was just opened");
+        *last_used = self.tick;
+        Ok(part)
+    }
+
+    /// Names are compared ignoring case, for case-insensitive file systems.
+    fn unique_name(&mut self, value: &str) -> String {
+        let base = file_name_part(value);
+        let mut name = base.clone();
+        let mut n = 1;
+        while !self.taken.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        name
+    }
+
+    fn close_least_recent(&mut self) -> Result<()> {
+        let value = self
+            .keyed
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(value, _)| value.clone());
+        if let Some((mut part, _)) = value.and_then(|v| self.keyed.remove(&v)) {
+            part.file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<usize> {
+        for part in self
+            .current
+            .iter_mut()
+            .chain(self.keyed.values_mut().map(|(part, _)| part))
+        {
+            part.file.flush()?;
+        }
+        Ok(self.count)
+    }
+}
+
+fn file_name_part(value: &str) -> String {
+    if value.is_empty() {
+        return "empty".to_string();
+    }
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+
+    fn split(args: &[&str], rows: &[[&str; 2]]) -> Result<Vec<(String, String)>> {
+        let dir = tempfile::tempdir()?;
+        let csv = test_opts(&[]).conversion.writer.csv;
+        let mut parts = Parts::new(
+            dir.path().to_path_buf(),
+            "p".to_string(),
+            &StringRecord::from(vec!["k", "v"]),
+            &csv,
+        )?;
+        for row in rows {
+            let bytes = parts.encode(&StringRecord::from(row.to_vec()))?;
+            let part = match args {
+                ["by"] => parts.keyed(row[0])?,
+                ["rows", n] => parts.sequential(Some(n.parse()?), None, bytes.len() as u64)?,
+                ["bytes", n] => parts.sequential(None, Some(n.parse()?), bytes.len() as u64)?,
+                _ => unreachable!(),
+            };
+            part.write(&bytes)?;
+        }
+        parts.finish()?;
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir.path())? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            files.push((name, std::fs::read_to_string(&path)?));
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    const ROWS: [[&str; 2]; 3] = [["a", "1"], ["b/c", "2"], ["a", "3"]];
+
+    #[test]
+    fn test_split_by_rows() -> Result<()> {
+        let files = split(&["rows", "2"], &ROWS)?;
+        assert_eq!(
+            files,
+            [
+                ("p_0001.csv".to_string(), "k,v\na,1\nb/c,2\n".to_string()),
+                ("p_0002.csv".to_string(), "k,v\na,3\n".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_bytes_and_value() -> Result<()> {
+        // header and one record are 8 bytes; a second record would not fit
+        let files = split(&["bytes", "12"], &ROWS)?;
+        assert_eq!(files.len(), 3);
+        let files = split(&["bytes", "1"], &ROWS)?;
+        assert_eq!(files.len(), 3);
+
+        let files = split(&["by"], &ROWS)?;
+        assert_eq!(
+            files[0],
+            ("p_a.csv".to_string(), "k,v\na,1\na,3\n".to_string())
+        );
+        assert_eq!(files[1].0, "p_b_c.csv");
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_many_values() -> Result<()> {
+        // every key comes back after its part was closed
+        let mut rows: Vec<[String; 2]> = (1..=2)
+            .flat_map(|v| (0..MAX_OPEN_PARTS * 2).map(move |i| [format!("k{}", i), v.to_string()]))
+            .collect();
+        rows.push(["b_c".to_string(), "3".to_string()]);
+        rows.push(["b/c".to_string(), "4".to_string()]);
+        rows.push(["B_C".to_string(), "5".to_string()]);
+        let rows: Vec<[&str; 2]> = rows.iter().map(|[k, v]| [k.as_str(), v.as_str()]).collect();
+        let files = split(&["by"], &rows)?;
+        assert_eq!(files.len(), MAX_OPEN_PARTS * 2 + 3);
+        assert!(files.contains(&("p_k0.csv".to_string(), "k,v\nk0,1\nk0,2\n".to_string())));
+        assert!(files.contains(&("p_b_c.csv".to_string(), "k,v\nb_c,3\n".to_string())));
+        assert!(files.contains(&("p_b_c_2.csv".to_string(), "k,v\nb/c,4\n".to_string())));
+        assert!(files.contains(&("p_B_C_3.csv".to_string(), "k,v\nB_C,5\n".to_string())));
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+use crate::cli::{ColumnType, CsvOpts, SqlOpts};
+use crate::process::csv_batch::expand_inputs;
+use crate::process::csv_convert::line_of;
+use crate::process::csv_reject::{MalformedRecord, Rejects};
+use crate::process::csv_source::Input;
+use crate::process::csv_types::{TypeInference, column_types, typed_value};
+use crate::process::csv_writer::{RecordWriter, open_output, record_writer};
+use anyhow::{Context, Result, bail};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{Connection, params_from_iter};
+use serde_json::{Map, Number, Value};
+use std::collections::HashSet;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// `rcli csv sql`: load every input into a SQLite table named after its file
+/// and write the rows of the query. With `--db` the tables are kept in that
+/// file; tables it already has are only replaced with `--force`.
+pub fn process_csv_sql(output: &str, opts: &CsvOpts, sql: &SqlOpts) -> Result<()> {
+    let mut conn = match &sql.db {
+        Some(path) => {
+            Connection::open(path).with_context(|| format!("opening {}", path.display()))?
+        }
+        None => Connection::open_in_memory()?,
+    };
+    let inputs = expand_inputs(&sql.input.paths)?;
+    let rejects = Rejects::new(opts);
+    let mut tables = HashSet::new();
+    for input in &inputs {
+        let table = table_name(input);
+        if !tables.insert(table.clone()) {
+            bail!("{} would replace table {} of another input", input, table);
+        }
+        if !sql.force && table_exists(&conn, &table)? {
+            bail!("Table {} already exists; pass --force to replace it", table);
+        }
+        load_table(&mut conn, &table, input, opts, sql, &rejects)
+            .with_context(|| format!("loading {}", input))?;
+        if inputs.len() == 1 && table != "t" {
+            conn.execute_batch(&format!(
+                "CREATE TEMP VIEW t AS SELECT * FROM {}",
+                quote(&table)
+            ))?;
+        }
+    }
+    rejects.finish()?;
+    let output =
+        open_output(output, &sql.writer).with_context(|| format!("creating {}", output))?;
+    let mut writer = record_writer(&sql.writer, BufWriter::new(output));
+    run_query(&conn, &sql.query, writer.as_mut())?;
+    writer.finish()
+}
+
+/// `data/sales 2024.csv` is loaded as `sales_2024`, stdin as `stdin`.
+fn table_name(input: &str) -> String {
+    if input == "-" {
+        return "stdin".to_string();
+    }
+    let stem = Path::new(input)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("t");
+    let mut name: String = stem
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    let mut stmt = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1 COLLATE NOCASE")?;
+    Ok(stmt.exists([table])?)
+}
+
+/// Give every column a distinct name: empty ones become `col1`, `col2`… by
+/// position, and repeats get a numeric suffix. Names are compared without
+/// ASCII case, as SQLite does.
+fn unique_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+    for (i, name) in names.enumerate() {
+        let name = if name.is_empty() {
+            format!("col{}", i + 1)
+        } else {
+            name.to_string()
+        };
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while unique.iter().any(|u| u.eq_ignore_ascii_case(&candidate)) {
+            n += 1;
+            candidate = format!("{}_{}", name, n);
+        }
+        unique.push(candidate);
+    }
+    unique
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Create `table` with the column types inferred from `input` and fill it
+/// in one transaction. Returns the number of rows loaded.
+fn load_table(
+    conn: &mut Connection,
+    table: &str,
+    input: &str,
+    opts: &CsvOpts,
+    sql: &SqlOpts,
+    rejects: &Rejects,
+) -> Result<usize> {
+    let source = Input::new(input, true)?;
+    let dates = sql.dates.parser(sql.typing.date_format.clone());
+    let (headers, records) = source.records(opts, &rejects.silent())?;
+    let mut inference = TypeInference::new(headers.len(), dates.clone());
+    for record in records {
+        for (i, field) in record?.iter().enumerate() {
+            inference.observe_field(i, field);
+        }
+    }
+    let types = column_types(&headers, Some(inference.types()), &sql.typing.types)?;
+
+    let columns: Vec<String> = unique_names(headers.iter())
+        .iter()
+        .zip(&types)
+        .map(|(name, ty)| format!("{} {}", quote(name), sql_type(*ty)))
+        .collect();
+    let placeholders = vec!["?"; headers.len()].join(", ");
+    let tx = conn.transaction()?;
+    tx.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {0}; CREATE TABLE {0} ({1});",
+        quote(table),
+        columns.join(", ")
+    ))?;
+    let mut count = 0;
+    {
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO {} VALUES ({})",
+            quote(table),
+            placeholders
+        ))?;
+        let (_, records) = source.records(opts, rejects)?;
+        for record in records {
+            let record = record?;
+            let values = types
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| typed_value(record.get(i).unwrap_or(""), *ty, &dates).map(sql_value))
+                .collect::<Result<Vec<_>>>();
+            match values {
+                Ok(values) => insert.execute(params_from_iter(values))?,
+                Err(e) => {
+                    let reason = format!("{:#}", e);
+                    rejects
+                        .handle(MalformedRecord::new(&record, line_of(&record), reason).into())?;
+                    continue;
+                }
+            };
+            count += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+fn sql_type(ty: Option<ColumnType>) -> &'static str {
+    match ty {
+        Some(ColumnType::Int | ColumnType::Bool) => "INTEGER",
+        Some(ColumnType::Float) => "REAL",
+        // normalized dates are ISO 8601, which sorts and compares as text
+        Some(ColumnType::Date | ColumnType::String) | None => "TEXT",
+    }
+}
+
+fn sql_value(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SqlValue::Text(s),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn json_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        ValueRef::Text(s) | ValueRef::Blob(s) => {
+            Value::String(String::from_utf8_lossy(s).into_owned())
+        }
+    }
+}
+
+/// Write every row of `query` as a record. Result columns sharing a name,
+/// as in `SELECT a.id, b.id`, get a numeric suffix.
+fn run_query(conn: &Connection, query: &str, writer: &mut dyn RecordWriter) -> Result<()> {
+    let mut stmt = conn.prepare(query).context("preparing the query")?;
+    let names = unique_names(stmt.column_names().into_iter());
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut record = Map::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            record.insert(name.clone(), json_value(row.get_ref(i)?));
+        }
+        writer.write_record(&Value::Object(record))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+
+    fn query(conn: &Connection, sql: &str) -> Result<Vec<Value>> {
+        let mut opts = test_opts(&[]).conversion.writer;
+        opts.format = crate::cli::OutputFormat::Jsonl;
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&opts, &mut buf);
+        run_query(conn, sql, writer.as_mut())?;
+        writer.finish()?;
+        drop(writer);
+        String::from_utf8(buf)?
+            .lines()
+            .map(|l| Ok(serde_json::from_str(l)?))
+            .collect()
+    }
+
+    #[test]
+    fn test_table_name() {
+        assert_eq!(table_name("data/sales 2024.csv"), "sales_2024");
+        assert_eq!(table_name("2024.csv"), "_2024");
+        assert_eq!(table_name("-"), "stdin");
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let names = unique_names(["a", "", "A", "a", "col2"].into_iter());
+        assert_eq!(names, ["a", "col2", "A_2", "a_3", "col2_2"]);
+    }
+
+    #[test]
+    fn test_existing_table_needs_force() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("items.csv");
+        std::fs::write(&input, "id,,id\n1,x,2\n")?;
+        let (input, db, out) = (
+            input.to_string_lossy().into_owned(),
+            dir.path().join("items.db").to_string_lossy().into_owned(),
+            dir.path().join("out.json").to_string_lossy().into_owned(),
+        );
+        let run = |force: &[&str]| {
+            let opts = test_opts(
+                &[
+                    &[
+                        "sql",
+                        "SELECT * FROM items",
+                        "-i",
+                        &input,
+                        "--db",
+                        &db,
+                        "-o",
+                        &out,
+                    ],
+                    force,
+                ]
+                .concat(),
+            );
+            let Some(crate::cli::CsvSubCommand::Sql(sql)) = &opts.cmd else {
+                unreachable!()
+            };
+            process_csv_sql(&out, &opts, sql)
+        };
+        run(&[])?;
+        let rows: Value = serde_json::from_str(&std::fs::read_to_string(&out)?)?;
+        assert_eq!(rows, serde_json::json!([{"id": 1, "col2": "x", "id_2": 2}]));
+        assert!(run(&[]).is_err());
+        run(&["--force"])
+    }
+
+    #[test]
+    fn test_load_and_query() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("items.csv");
+        std::fs::write(
+            &path,
+            "id,name,price,ok\n1,a,1.5,true\n2,b,,false\n3,a,2,true\n",
+        )?;
+        let path = path.to_string_lossy().into_owned();
+        let opts = test_opts(&["sql", "SELECT 1", "-i", &path]);
+        let Some(crate::cli::CsvSubCommand::Sql(sql)) = &opts.cmd else {
+            unreachable!()
+        };
+        let mut conn = Connection::open_in_memory()?;
+        let rejects = Rejects::new(&opts);
+        assert_eq!(
+            load_table(&mut conn, "items", &path, &opts, sql, &rejects)?,
+            3
+        );
+
+        let rows = query(
+            &conn,
+            "SELECT name, count(*) AS n, sum(price) AS total FROM items GROUP BY 1",
+        )?;
+        assert_eq!(
+            rows[0],
+            serde_json::json!({"name": "a", "n": 2, "total": 3.5})
+        );
+        let rows = query(
+            &conn,
+            "SELECT typeof(id), typeof(price), ok FROM items WHERE id = 2",
+        )?;
+        assert_eq!(
+            rows[0],
+            serde_json::json!({"typeof(id)": "integer", "typeof(price)": "null", "ok": 0})
+        );
+        let rows = query(
+            &conn,
+            "SELECT a.id, b.id FROM items a JOIN items b USING (id) LIMIT 1",
+        )?;
+        assert_eq!(rows[0], serde_json::json!({"id": 1, "id_2": 1}));
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cli::{ColumnType, CsvOpts, StatsOpts};
+use crate::process::csv_convert::prepared_records;
+use crate::process::csv_dates::DateParser;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_sort::numeric_cmp;
+use crate::process::csv_source::Input;
+use crate::process::csv_types::{TypeInference, typed_value};
+use crate::process::csv_writer::{open_output, record_writer};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde_json::{Map, Value, json};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::BufWriter;
+
+/// `rcli csv stats`: one output record per input column. Every distinct value
+/// is counted, so memory grows with the number of distinct values.
+pub fn process_csv_stats(
+    input: &str,
+    output: &str,
+    opts: &CsvOpts,
+    stats: &StatsOpts,
+) -> Result<()> {
+    let input = Input::new(input, stats.records.keep_last())?;
+    let rejects = Rejects::new(opts);
+    let (headers, records) =
+        prepared_records(|r| input.records(opts, r), &stats.records, &rejects)?;
+    let mut profile = Profile::new(headers, stats.dates.parser(None));
+    for record in records {
+        profile.add(&record?);
+    }
+    let output =
+        open_output(output, &stats.writer).with_context(|| format!("creating {}", output))?;
+    let mut writer = record_writer(&stats.writer, BufWriter::new(output));
+    for value in profile.report(stats.top) {
+        writer.write_record(&value)?;
+    }
+    writer.finish()?;
+    rejects.finish()
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    empty: u64,
+    max_length: usize,
+    values: HashMap<String, u64>,
+}
+
+struct Profile {
+    headers: StringRecord,
+    columns: Vec<ColumnStats>,
+    inference: TypeInference,
+    dates: DateParser,
+}
+
+impl Profile {
+    fn new(headers: StringRecord, dates: DateParser) -> Self {
+        let columns = headers.iter().map(|_| ColumnStats::default()).collect();
+        let inference = TypeInference::new(headers.len(), dates.clone());
+        Self {
+            headers,
+            columns,
+            inference,
+            dates,
+        }
+    }
+
+    /// Missing fields of a short record count as empty.
+    fn add(&mut self, record: &StringRecord) {
+        for (i, column) in self.columns.iter_mut().enumerate() {
+            let field = record.get(i).unwrap_or("");
+            if field.trim().is_empty() {
+                column.empty += 1;
+                continue;
+            }
+            self.inference.observe_field(i, field);
+            column.max_length = column.max_length.max(field.chars().count());
+            match column.values.get_mut(field) {
+                Some(n) => *n += 1,
+                None => {
+                    column.values.insert(field.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    fn report(&self, top: usize) -> Vec<Value> {
+        let types = self.inference.types();
+        self.headers
+            .iter()
+            .zip(&self.columns)
+            .zip(types)
+            .map(|((name, column), ty)| {
+                let count: u64 = column.values.values().sum();
+                let min = column.values.keys().min_by(|a, b| self.compare(ty, a, b));
+                let max = column.values.keys().max_by(|a, b| self.compare(ty, a, b));
+                let typed = |v: Option<&String>| {
+                    v.map_or(Value::Null, |v| {
+                        typed_value(v, Some(ty), &self.dates).unwrap_or(Value::String(v.clone()))
+                    })
+                };
+                let mut frequent: Vec<(&String, &u64)> = column.values.iter().collect();
+                frequent.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+                let top: Map<String, Value> = frequent
+                    .into_iter()
+                    .take(top)
+                    .map(|(v, n)| (v.clone(), json!(n)))
+                    .collect();
+                json!({
+                    "column": name,
+                    "type": ty.to_string(),
+                    "count": count,
+                    "empty": column.empty,
+                    "distinct": column.values.len(),
+                    "min": typed(min),
+                    "max": typed(max),
+                    "max_length": column.max_length,
+                    "top": top,
+                })
+            })
+            .collect()
+    }
+
+    /// Order values the way their inferred type does.
+    fn compare(&self, ty: ColumnType, a: &str, b: &str) -> Ordering {
+        match ty {
+            ColumnType::Int | ColumnType::Float => numeric_cmp(a, b),
+            ColumnType::Date => self.dates.parse(a).cmp(&self.dates.parse(b)),
+            ColumnType::Bool | ColumnType::String => a.cmp(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(headers: &[&str], rows: &[&[&str]]) -> Vec<Value> {
+        let mut profile = Profile::new(
+            StringRecord::from(headers.to_vec()),
+            DateParser::new(50, false, None),
+        );
+        for row in rows {
+            profile.add(&StringRecord::from(row.to_vec()));
+        }
+        profile.report(2)
+    }
+
+    #[test]
+    fn test_column_profile() {
+        let report = profile(
+            &["Date", "n", "Notes"],
+            &[
+                &["6/16/16", "10", "x"],
+                &["January 4 1965", "9", ""],
+                &["12/1/93", "10", " "],
+            ],
+        );
+        assert_eq!(
+            report[0],
+            json!({"column": "Date", "type": "date", "count": 3, "empty": 0, "distinct": 3,
+                   "min": "1965-01-04", "max": "2016-06-16", "max_length": 14,
+                   "top": {"12/1/93": 1, "6/16/16": 1}})
+        );
+        assert_eq!(report[1]["min"], json!(9));
+        assert_eq!(report[1]["top"], json!({"10": 2, "9": 1}));
+        assert_eq!(report[2]["empty"], json!(2));
+        assert_eq!(report[2]["type"], json!("string"));
+    }
+
+    #[test]
+    fn test_all_empty_column() {
+        let report = profile(&["a", "b"], &[&["1"], &["2"]]);
+        assert_eq!(report[1]["empty"], json!(2));
+        assert_eq!(report[1]["min"], Value::Null);
+        assert_eq!(report[1]["top"], json!({}));
+    }
+}
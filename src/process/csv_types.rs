@@ -0,0 +1,228 @@
+use crate::cli::ColumnType;
+use crate::process::csv_columns::column_index;
+use crate::process::csv_dates::DateParser;
+use anyhow::{Context, Result, anyhow};
+use csv::StringRecord;
+use serde_json::{Number, Value};
+
+#[derive(Debug, Clone, Copy)]
+struct Candidates {
+    seen: bool,
+    int: bool,
+    float: bool,
+    bool: bool,
+    date: bool,
+}
+
+/// Narrows each column down to the most specific type every non-empty value
+/// in it can be parsed as.
+#[derive(Debug)]
+pub struct TypeInference {
+    columns: Vec<Candidates>,
+    dates: DateParser,
+}
+
+impl TypeInference {
+    pub fn new(width: usize, dates: DateParser) -> Self {
+        let all = Candidates {
+            seen: false,
+            int: true,
+            float: true,
+            bool: true,
+            date: true,
+        };
+        Self {
+            columns: vec![all; width],
+            dates,
+        }
+    }
+
+    pub fn observe_field(&mut self, column: usize, field: &str) {
+        let Some(column) = self.columns.get_mut(column) else {
+            return;
+        };
+        if field.is_empty() {
+            return;
+        }
+        column.seen = true;
+        column.int = column.int && parse_int(field).is_some();
+        column.float = column.float && parse_float(field).is_some();
+        column.bool = column.bool && parse_bool(field).is_some();
+        column.date = column.date && self.dates.parse(field).is_some();
+    }
+
+    pub fn types(&self) -> Vec<ColumnType> {
+        self.columns
+            .iter()
+            .map(|c| {
+                if !c.seen {
+                    ColumnType::String
+                } else if c.int {
+                    ColumnType::Int
+                } else if c.float {
+                    ColumnType::Float
+                } else if c.bool {
+                    ColumnType::Bool
+                } else if c.date {
+                    ColumnType::Date
+                } else {
+                    ColumnType::String
+                }
+            })
+            .collect()
+    }
+}
+
+/// Resolve the type of every column from the inferred types (if any) and the
+/// `--type` overrides. `None` means the field is emitted verbatim.
+pub fn column_types(
+    headers: &StringRecord,
+    inferred: Option<Vec<ColumnType>>,
+    overrides: &[(String, ColumnType)],
+) -> Result<Vec<Option<ColumnType>>> {
+    let mut types = match inferred {
+        Some(types) => types.into_iter().map(Some).collect(),
+        None => vec![None; headers.len()],
+    };
+    for (name, ty) in overrides {
+        let idx = column_index(headers, name).context("in --type")?;
+        types[idx] = Some(*ty);
+    }
+    Ok(types)
+}
+
+/// Convert a raw field to a JSON value of the given type. Empty fields of a
+/// typed column become `null`; dates are normalized by `dates`.
+pub fn typed_value(field: &str, ty: Option<ColumnType>, dates: &DateParser) -> Result<Value> {
+    let Some(ty) = ty else {
+        return Ok(Value::String(field.to_string()));
+    };
+    if field.is_empty() {
+        return Ok(Value::Null);
+    }
+    let value = match ty {
+        ColumnType::String => Some(Value::String(field.to_string())),
+        ColumnType::Int => parse_int(field).map(Value::from),
+        ColumnType::Float => parse_float(field)
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+        ColumnType::Bool => parse_bool(field).map(Value::Bool),
+        ColumnType::Date => return dates.normalize(field).map(Value::String),
+    };
+    value.ok_or_else(|| anyhow!("Cannot parse {:?} as {}", field, ty))
+}
+
+fn has_leading_zero(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+pub fn parse_int(s: &str) -> Option<i64> {
+    // "007" or "+7" would not survive a round trip, so keep them as strings
+    if s.starts_with('+') || has_leading_zero(s) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+pub fn parse_float(s: &str) -> Option<f64> {
+    let numeric = s.bytes().any(|b| b.is_ascii_digit())
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+    if !numeric || s.starts_with('+') || has_leading_zero(s) {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dates() -> DateParser {
+        DateParser::new(50, false, None)
+    }
+
+    fn observe(inference: &mut TypeInference, fields: &[&str]) {
+        for (i, field) in fields.iter().enumerate() {
+            inference.observe_field(i, field);
+        }
+    }
+
+    #[test]
+    fn test_infer_column_types() {
+        let mut inference = TypeInference::new(6, dates());
+        observe(&mut inference, &["1", "1.5", "true", "x", "", "6/16/16"]);
+        observe(
+            &mut inference,
+            &["-2", "3", "FALSE", "4", "", "January 4 1965"],
+        );
+        observe(&mut inference, &["", "", "", "", "", ""]);
+        assert_eq!(
+            inference.types(),
+            vec![
+                ColumnType::Int,
+                ColumnType::Float,
+                ColumnType::Bool,
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::Date,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leading_zeros_stay_strings() {
+        let mut inference = TypeInference::new(2, dates());
+        observe(&mut inference, &["007", "01.5"]);
+        assert_eq!(
+            inference.types(),
+            vec![ColumnType::String, ColumnType::String]
+        );
+    }
+
+    #[test]
+    fn test_typed_value() -> Result<()> {
+        assert_eq!(
+            typed_value("42", Some(ColumnType::Int), &dates())?,
+            json!(42)
+        );
+        assert_eq!(
+            typed_value("2.5", Some(ColumnType::Float), &dates())?,
+            json!(2.5)
+        );
+        assert_eq!(
+            typed_value("True", Some(ColumnType::Bool), &dates())?,
+            json!(true)
+        );
+        assert_eq!(
+            typed_value("", Some(ColumnType::String), &dates())?,
+            Value::Null
+        );
+        assert_eq!(typed_value("", None, &dates())?, json!(""));
+        assert!(typed_value("abc", Some(ColumnType::Int), &dates()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides() -> Result<()> {
+        let headers = StringRecord::from(vec!["id", "name"]);
+        let overrides = vec![("id".to_string(), ColumnType::String)];
+        let types = column_types(&headers, Some(vec![ColumnType::Int; 2]), &overrides)?;
+        assert_eq!(types, vec![Some(ColumnType::String), Some(ColumnType::Int)]);
+        let unknown = vec![("nope".to_string(), ColumnType::Int)];
+        assert!(column_types(&headers, None, &unknown).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use crate::cli::{ColumnType, CsvOpts, ReportFormat, ValidateOpts};
+use crate::process::csv_convert::line_of;
+use crate::process::csv_dates::DateParser;
+use crate::process::csv_reject::Rejects;
+use crate::process::csv_source::Input;
+use crate::process::csv_types::typed_value;
+use crate::process::csv_writer::cell_text;
+use crate::utils::{get_reader, get_writer};
+use anyhow::{Context, Result, bail};
+use csv::StringRecord;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+/// The `--schema` file:
+///
+/// ```yaml
+/// columns:
+///   Identifier: { required: true, unique: true, pattern: '\d{4}\.\d+\.\d+' }
+///   Date: { type: date, min: 1900-01-01 }
+///   Reproduction: { enum: [orig, repro] }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    columns: BTreeMap<String, ColumnRule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColumnRule {
+    /// The column must exist and have no empty values.
+    #[serde(default)]
+    required: bool,
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    /// Must match the whole value.
+    pattern: Option<String>,
+    #[serde(rename = "enum")]
+    values: Option<Vec<Value>>,
+    #[serde(default)]
+    unique: bool,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub line: u64,
+    pub column: String,
+    pub value: String,
+    pub message: String,
+}
+
+/// `rcli csv validate`: report every violation of the schema and fail when
+/// there is at least one.
+pub fn process_csv_validate(
+    input: &str,
+    output: &str,
+    opts: &CsvOpts,
+    validate: &ValidateOpts,
+) -> Result<()> {
+    let schema: Schema = serde_yaml::from_reader(get_reader(&validate.schema)?)
+        .with_context(|| format!("reading schema {}", validate.schema))?;
+    let input = Input::new(input, false)?;
+    let rejects = Rejects::new(opts);
+    let (headers, records) = input.records(opts, &rejects)?;
+    let (mut validator, mut violations) =
+        Validator::new(&schema, &headers, validate.dates.parser(None))?;
+    let mut count = 0;
+    for record in records {
+        violations.extend(validator.check(&record?));
+        count += 1;
+    }
+    let mut writer = get_writer(output).with_context(|| format!("creating {}", output))?;
+    match validate.report {
+        ReportFormat::Human => {
+            for v in &violations {
+                writeln!(
+                    writer,
+                    "line {}, column {:?}, value {:?}: {}",
+                    v.line, v.column, v.value, v.message
+                )?;
+            }
+            writeln!(
+                writer,
+                "{} records checked, {} violations",
+                count,
+                violations.len()
+            )?;
+        }
+        ReportFormat::Json => {
+            let report = json!({
+                "valid": violations.is_empty(),
+                "records": count,
+                "violations": violations,
+            });
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    rejects.finish()?;
+    if !violations.is_empty() {
+        bail!("{} violations found", violations.len());
+    }
+    Ok(())
+}
+
+/// A column rule resolved against the headers, with the values seen so far
+/// for `unique`.
+struct ColumnCheck {
+    name: String,
+    index: usize,
+    required: bool,
+    ty: Option<ColumnType>,
+    pattern: Option<Regex>,
+    values: Option<Vec<String>>,
+    min: Option<String>,
+    max: Option<String>,
+    seen: Option<HashMap<String, u64>>,
+}
+
+pub struct Validator {
+    checks: Vec<ColumnCheck>,
+    dates: DateParser,
+}
+
+impl Validator {
+    /// Also returns the violations of the header itself: required columns
+    /// that are missing. Schema keys are header names only, never positions.
+    pub fn new(
+        schema: &Schema,
+        headers: &StringRecord,
+        dates: DateParser,
+    ) -> Result<(Self, Vec<Violation>)> {
+        let mut checks = Vec::new();
+        let mut missing = Vec::new();
+        for (name, rule) in &schema.columns {
+            let Some(index) = headers.iter().position(|h| h == name) else {
+                if rule.required {
+                    missing.push(Violation {
+                        line: 1,
+                        column: name.clone(),
+                        value: String::new(),
+                        message: "missing column".to_string(),
+                    });
+                }
+                continue;
+            };
+            let ty = rule
+                .ty
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| format!("schema for {:?}", name))?;
+            let pattern = rule
+                .pattern
+                .as_deref()
+                .map(|p| Regex::new(&format!("^(?:{})$", p)))
+                .transpose()
+                .with_context(|| format!("schema for {:?}", name))?;
+            checks.push(ColumnCheck {
+                name: name.clone(),
+                index,
+                required: rule.required,
+                ty,
+                pattern,
+                values: rule
+                    .values
+                    .as_ref()
+                    .map(|v| v.iter().map(cell_text).collect()),
+                min: rule.min.as_ref().map(cell_text),
+                max: rule.max.as_ref().map(cell_text),
+                seen: rule.unique.then(HashMap::new),
+            });
+        }
+        checks.sort_by_key(|c| c.index);
+        Ok((Self { checks, dates }, missing))
+    }
+
+    pub fn check(&mut self, record: &StringRecord) -> Vec<Violation> {
+        let line = line_of(record);
+        let mut violations = Vec::new();
+        for check in &mut self.checks {
+            let field = record.get(check.index).unwrap_or("");
+            let mut fail = |message: String| {
+                violations.push(Violation {
+                    line,
+                    column: check.name.clone(),
+                    value: field.to_string(),
+                    message,
+                })
+            };
+            if field.is_empty() {
+                if check.required {
+                    fail("value is required".to_string());
+                }
+                continue;
+            }
+            if let Some(ty) = check.ty
+                && typed_value(field, Some(ty), &self.dates).is_err()
+            {
+                fail(format!("not a valid {}", ty));
+                continue;
+            }
+            if let Some(pattern) = &check.pattern
+                && !pattern.is_match(field)
+            {
+                fail(format!("does not match {}", pattern.as_str()));
+            }
+            if let Some(values) = &check.values
+                && !values.iter().any(|v| v == field)
+            {
+                fail(format!("not one of: {}", values.join(", ")));
+            }
+            if let Some(min) = &check.min
+                && compare(check.ty, &self.dates, field, min) == Ordering::Less
+            {
+                fail(format!("less than {}", min));
+            }
+            if let Some(max) = &check.max
+                && compare(check.ty, &self.dates, field, max) == Ordering::Greater
+            {
+                fail(format!("greater than {}", max));
+            }
+            if let Some(seen) = &mut check.seen {
+                match seen.get(field) {
+                    Some(first) => fail(format!("duplicate of line {}", first)),
+                    None => {
+                        seen.insert(field.to_string(), line);
+                    }
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// Compare a value with a `min`/`max` bound: as dates for date columns, as
+/// numbers when both are numbers, as text otherwise.
+fn compare(ty: Option<ColumnType>, dates: &DateParser, field: &str, bound: &str) -> Ordering {
+    if ty == Some(ColumnType::Date)
+        && let (Some(a), Some(b)) = (dates.parse(field), dates.parse(bound))
+    {
+        return a.cmp(&b);
+    }
+    match (field.trim().parse::<f64>(), bound.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => field.cmp(bound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::Position;
+
+    const SCHEMA: &str = r#"
+columns:
+  Identifier: { required: true, unique: true, pattern: '\d{4}\.\d+\.\d+' }
+  Date: { type: date, min: 1960-01-01 }
+  Price: { type: float, min: 0, max: 100 }
+  Reproduction: { enum: [orig, repro] }
+  Notes: { required: true }
+  Missing: { required: true }
+  Optional: { type: int }
+"#;
+
+    fn validate(rows: &[&[&str]]) -> Result<Vec<Violation>> {
+        let schema: Schema = serde_yaml::from_str(SCHEMA)?;
+        let headers =
+            StringRecord::from(vec!["Identifier", "Date", "Price", "Reproduction", "Notes"]);
+        let (mut validator, mut violations) =
+            Validator::new(&schema, &headers, DateParser::new(50, false, None))?;
+        for (i, row) in rows.iter().enumerate() {
+            let mut record = StringRecord::from(row.to_vec());
+            let mut position = Position::new();
+            position.set_line(i as u64 + 2);
+            record.set_position(Some(position));
+            violations.extend(validator.check(&record));
+        }
+        Ok(violations)
+    }
+
+    fn messages(violations: &[Violation]) -> Vec<(u64, &str, &str)> {
+        violations
+            .iter()
+            .map(|v| (v.line, v.column.as_str(), v.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_rows() -> Result<()> {
+        let violations = validate(&[&["2016.1.1", "6/16/16", "9.5", "orig", "x"]])?;
+        assert_eq!(messages(&violations), [(1, "Missing", "missing column")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_violations() -> Result<()> {
+        let violations = validate(&[
+            &["2016.1.1", "someday", "-1", "copy", ""],
+            &["2016.1.1", "1/4/55", "100", "repro", "x"],
+        ])?;
+        assert_eq!(
+            messages(&violations[1..]),
+            [
+                (2, "Date", "not a valid date"),
+                (2, "Price", "less than 0"),
+                (2, "Reproduction", "not one of: orig, repro"),
+                (2, "Notes", "value is required"),
+                (3, "Identifier", "duplicate of line 2"),
+                (3, "Date", "less than 1960-01-01"),
+            ]
+        );
+        assert_eq!(violations[1].value, "someday");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_schema() {
+        assert!(serde_yaml::from_str::<Schema>("columns: { a: { typo: true } }").is_err());
+        let schema: Schema = serde_yaml::from_str("columns: { a: { type: time } }").unwrap();
+        let headers = StringRecord::from(vec!["a"]);
+        assert!(Validator::new(&schema, &headers, DateParser::new(50, false, None)).is_err());
+    }
+
+    #[test]
+    fn test_numeric_column_name_is_not_a_position() -> Result<()> {
+        let schema: Schema =
+            serde_yaml::from_str("columns: { '1': { required: true, type: int } }")?;
+        let headers = StringRecord::from(vec!["a"]);
+        let (_, violations) = Validator::new(&schema, &headers, DateParser::new(50, false, None))?;
+        assert_eq!(messages(&violations), [(1, "1", "missing column")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,538 @@
+use crate::cli::{ColumnType, CsvWriterOpts, OutputFormat, QuoteStyle, WriterOpts};
+use crate::process::csv_columnar::ColumnarWriter;
+use crate::process::csv_show::{Pager, TableWriter};
+use crate::utils::get_writer;
+use anyhow::{Result, bail};
+use serde_json::{Map, Value};
+use std::io::{IsTerminal, Write};
+
+/// Writes converted records one at a time, so the whole document never has to
+/// be held in memory.
+pub trait RecordWriter {
+    /// The top-level fields of the records to come, in order, when they are
+    /// known up front. Formats that need a schema use them instead of
+    /// guessing it from the first records.
+    fn set_columns(&mut self, _columns: &[(String, FieldType)]) {}
+    fn write_record(&mut self, record: &Value) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// The type of a top-level field, as far as it is known before the records.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    /// A scalar of this column type; the field's text when `None`.
+    Scalar(Option<ColumnType>),
+    /// Arrays and nested objects, whose shape only shows in the values.
+    Nested,
+}
+
+pub struct JsonWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+}
+
+pub struct YamlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+pub struct TomlWriter<W: Write> {
+    writer: W,
+}
+
+pub struct XmlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+pub struct MarkdownWriter<W: Write> {
+    writer: W,
+    headers: Vec<String>,
+}
+
+pub struct HtmlWriter<W: Write> {
+    writer: W,
+    headers: Vec<String>,
+}
+
+pub struct CsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    headers: Vec<String>,
+}
+
+pub fn record_writer<'a, W: Write + Send + 'a>(
+    opts: &WriterOpts,
+    writer: W,
+) -> Box<dyn RecordWriter + 'a> {
+    match opts.format {
+        OutputFormat::Json => Box::new(JsonWriter::new(writer)),
+        OutputFormat::Jsonl => Box::new(JsonlWriter::new(writer)),
+        OutputFormat::Yaml => Box::new(YamlWriter::new(writer)),
+        OutputFormat::Table => Box::new(TableWriter::new(writer, opts)),
+        OutputFormat::Toml => Box::new(TomlWriter::new(writer)),
+        OutputFormat::Xml => Box::new(XmlWriter::new(writer)),
+        OutputFormat::Markdown => Box::new(MarkdownWriter::new(writer)),
+        OutputFormat::Html => Box::new(HtmlWriter::new(writer)),
+        OutputFormat::Csv => Box::new(CsvWriter::new(writer, opts)),
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            Box::new(ColumnarWriter::new(writer, opts.format, opts.compression))
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Parquet and Arrow need a type for every column up front.
+    pub fn is_columnar(self) -> bool {
+        matches!(self, OutputFormat::Parquet | OutputFormat::Arrow)
+    }
+}
+
+/// Open `output` for writing; tables shown on a terminal go through a pager.
+pub fn open_output(output: &str, opts: &WriterOpts) -> Result<Box<dyn Write + Send>> {
+    let paged = matches!(opts.format, OutputFormat::Table)
+        && output == "-"
+        && !opts.no_pager
+        && std::io::stdout().is_terminal();
+    if paged && let Ok(pager) = Pager::spawn() {
+        return Ok(Box::new(pager));
+    }
+    get_writer(output)
+}
+
+impl<W: Write> RecordWriter for JsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        self.writer
+            .write_all(if self.count == 0 { b"[\n" } else { b",\n" })?;
+        let pretty = serde_json::to_string_pretty(record)?;
+        for (i, line) in pretty.lines().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            write!(self.writer, "  {}", line)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer
+            .write_all(if self.count == 0 { b"[]" } else { b"\n]" })?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for JsonlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for YamlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        // a one-item sequence per record concatenates into one valid sequence
+        self.writer
+            .write_all(serde_yaml::to_string(&[record])?.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for TomlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        // TOML has no null, so those keys are left out
+        let mut doc = Map::new();
+        doc.insert(
+            "record".to_string(),
+            Value::Array(vec![without_nulls(record)]),
+        );
+        self.writer.write_all(toml::to_string(&doc)?.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for XmlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if self.count == 0 {
+            self.writer
+                .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records>\n")?;
+        }
+        let mut element = String::new();
+        xml_element(&mut element, "record", record, 1);
+        self.writer.write_all(element.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer
+                .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records/>\n")?;
+        } else {
+            self.writer.write_all(b"</records>\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for MarkdownWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if self.headers.is_empty() {
+            self.headers = record_headers(record)?;
+            let header: Vec<String> = self.headers.iter().map(|h| markdown_escape(h)).collect();
+            writeln!(self.writer, "| {} |", header.join(" | "))?;
+            writeln!(self.writer, "|{}", " --- |".repeat(self.headers.len()))?;
+        }
+        let row: Vec<String> = record_cells(record, &self.headers)
+            .iter()
+            .map(|cell| markdown_escape(cell))
+            .collect();
+        writeln!(self.writer, "| {} |", row.join(" | "))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for HtmlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if self.headers.is_empty() {
+            self.headers = record_headers(record)?;
+            writeln!(self.writer, "<table>\n  <thead>\n    <tr>")?;
+            for header in &self.headers {
+                writeln!(self.writer, "      <th>{}</th>", html_escape(header))?;
+            }
+            writeln!(self.writer, "    </tr>\n  </thead>\n  <tbody>")?;
+        }
+        writeln!(self.writer, "    <tr>")?;
+        for cell in record_cells(record, &self.headers) {
+            writeln!(self.writer, "      <td>{}</td>", html_escape(&cell))?;
+        }
+        writeln!(self.writer, "    </tr>")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.headers.is_empty() {
+            writeln!(self.writer, "<table>\n</table>")?;
+        } else {
+            writeln!(self.writer, "  </tbody>\n</table>")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for CsvWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if self.headers.is_empty() {
+            self.headers = record_headers(record)?;
+            self.writer.write_record(&self.headers)?;
+        }
+        self.writer
+            .write_record(record_cells(record, &self.headers))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> YamlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> TomlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> MarkdownWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> HtmlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W, opts: &WriterOpts) -> Self {
+        Self {
+            writer: opts.csv.builder().from_writer(writer),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl CsvWriterOpts {
+    /// A CSV writer set up with `--out-delimiter` and `--quote-style`.
+    pub fn builder(&self) -> csv::WriterBuilder {
+        let quote_style = match self.quote_style {
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        };
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(self.out_delimiter)
+            .quote_style(quote_style);
+        builder
+    }
+}
+
+/// Plain-text form of a value for tabular formats; nested values stay JSON.
+pub fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn record_headers(record: &Value) -> Result<Vec<String>> {
+    match record {
+        Value::Object(map) => Ok(map.keys().cloned().collect()),
+        _ => bail!("Tabular output expects records to be objects"),
+    }
+}
+
+fn record_cells(record: &Value, headers: &[String]) -> Vec<String> {
+    headers
+        .iter()
+        .map(|h| record.get(h).map(cell_text).unwrap_or_default())
+        .collect()
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(without_nulls)
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
+
+fn xml_element(out: &mut String, name: &str, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let name = xml_name(name);
+    match value {
+        Value::Null => out.push_str(&format!("{}<{}/>\n", indent, name)),
+        Value::Object(map) => {
+            out.push_str(&format!("{}<{}>\n", indent, name));
+            for (key, child) in map {
+                xml_element(out, key, child, depth + 1);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, name));
+        }
+        Value::Array(items) => {
+            out.push_str(&format!("{}<{}>\n", indent, name));
+            for item in items {
+                xml_element(out, "item", item, depth + 1);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, name));
+        }
+        v => out.push_str(&format!(
+            "{}<{}>{}</{}>\n",
+            indent,
+            name,
+            html_escape(&cell_text(v)),
+            name
+        )),
+    }
+}
+
+/// Turn a column name into a valid XML element name, e.g. `Accession No`
+/// becomes `Accession_No`.
+fn xml_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let starts_ok = out.starts_with(|c: char| c.is_alphabetic() || c == '_');
+    if !starts_ok || out.to_ascii_lowercase().starts_with("xml") {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn markdown_escape(s: &str) -> String {
+    s.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_opts;
+    use serde_json::json;
+
+    fn write_all(format: OutputFormat, records: &[Value]) -> Result<String> {
+        let mut opts = test_opts(&[]).conversion.writer;
+        opts.format = format;
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&opts, &mut buf);
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_streamed_output_matches_whole_document() -> Result<()> {
+        let records = vec![
+            json!({"a": "1", "b": {"c": [1, 2]}}),
+            json!({"a": "multi\nline", "b": null}),
+        ];
+        let json = write_all(OutputFormat::Json, &records)?;
+        assert_eq!(json, serde_json::to_string_pretty(&records)?);
+        let yaml = write_all(OutputFormat::Yaml, &records)?;
+        assert_eq!(yaml, serde_yaml::to_string(&records)?);
+        let jsonl = write_all(OutputFormat::Jsonl, &records)?;
+        let lines = jsonl
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(lines, records);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_output_is_valid() -> Result<()> {
+        assert_eq!(write_all(OutputFormat::Json, &[])?, "[]");
+        let yaml: Vec<Value> = serde_yaml::from_str(&write_all(OutputFormat::Yaml, &[])?)?;
+        assert!(yaml.is_empty());
+        assert_eq!(write_all(OutputFormat::Jsonl, &[])?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tabular_formats() -> Result<()> {
+        let records = vec![
+            json!({"name": "a|b", "Accession No": 1}),
+            json!({"name": "<x>", "Accession No": null}),
+        ];
+        assert_eq!(
+            write_all(OutputFormat::Markdown, &records)?,
+            "| name | Accession No |\n| --- | --- |\n| a\\|b | 1 |\n| <x> |  |\n"
+        );
+        assert!(write_all(OutputFormat::Html, &records)?.contains("<td>&lt;x&gt;</td>"));
+        assert_eq!(
+            write_all(OutputFormat::Csv, &records)?,
+            "name,Accession No\na|b,1\n<x>,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_formats() -> Result<()> {
+        let records = vec![json!({"Accession No": 1, "note": null, "tags": ["a", "b"]})];
+        let toml = write_all(OutputFormat::Toml, &records)?;
+        let parsed: toml::Table = toml::from_str(&toml)?;
+        assert_eq!(parsed["record"][0]["Accession No"].as_integer(), Some(1));
+        assert!(parsed["record"][0].get("note").is_none());
+
+        let xml = write_all(OutputFormat::Xml, &records)?;
+        assert!(xml.contains("<Accession_No>1</Accession_No>"));
+        assert!(xml.contains("<note/>"));
+        assert!(xml.contains("<item>a</item>"));
+        assert_eq!(xml_name("1st"), "_1st");
+        Ok(())
+    }
+}
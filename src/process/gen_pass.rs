@@ -1,4 +1,4 @@
-use rand::prelude::{SliceRandom};
+use rand::prelude::SliceRandom;
 use rand::rngs::OsRng;
 
 const UPPER: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
@@ -37,10 +37,7 @@ pub fn process_genpass(
     }
 
     for _ in 0..(length - password.len() as u8) {
-        let c = chars
-            .choose(&mut rng)
-            .expect("chars won't be empty")
-            .clone();
+        let c = *chars.choose(&mut rng).expect("chars won't be empty");
         // let idx = rng.gen_range(0.. chars.len());
         password.push(c);
     }
@@ -1,11 +1,41 @@
 mod b64;
+mod csv_agg;
+mod csv_batch;
+mod csv_columnar;
+mod csv_columns;
 mod csv_convert;
+mod csv_dates;
+mod csv_diff;
+mod csv_filter;
+mod csv_join;
+mod csv_mask;
+mod csv_merge;
+mod csv_nest;
+mod csv_reject;
+mod csv_show;
+mod csv_sort;
+mod csv_source;
+mod csv_split;
+mod csv_sql;
+mod csv_stats;
+mod csv_types;
+mod csv_validate;
+mod csv_writer;
 mod gen_pass;
-mod text;
 mod http_serve;
+mod text;
 
 pub use b64::{process_decode, process_encode};
+pub use csv_agg::process_csv_agg;
+pub use csv_batch::process_csv_batch;
 pub use csv_convert::process_csv;
+pub use csv_diff::process_csv_diff;
+pub use csv_join::process_csv_join;
+pub use csv_merge::process_csv_merge;
+pub use csv_split::process_csv_split;
+pub use csv_sql::process_csv_sql;
+pub use csv_stats::process_csv_stats;
+pub use csv_validate::process_csv_validate;
 pub use gen_pass::process_genpass;
-pub use text::{process_text_sign, process_text_verify,process_generate};
 pub use http_serve::process_http_serve;
+pub use text::{process_generate, process_text_sign, process_text_verify};
@@ -1,13 +1,14 @@
 use crate::cli::TextSignFormat;
 use crate::get_reader;
-use anyhow::Result;
-use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use anyhow::{Result, anyhow};
 use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use rand::rngs::OsRng;
 use std::fs;
 use std::io::Read;
 use std::path::Path;
-use rand::rngs::OsRng;
 
 pub trait TestSign {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>>;
@@ -57,7 +58,12 @@ pub fn process_text_sign(input: &str, key: &str, format: TextSignFormat) -> Resu
     Ok(signed)
 }
 
-pub fn process_text_verify(input: &str, key: &str, format: TextSignFormat,sig:&str) -> Result<bool> {
+pub fn process_text_verify(
+    input: &str,
+    key: &str,
+    format: TextSignFormat,
+    sig: &str,
+) -> Result<bool> {
     let mut reader = get_reader(input)?;
     let mut buffer = Vec::new();
     reader.read_to_end(&mut buffer)?;
@@ -81,7 +87,6 @@ pub fn process_generate(format: TextSignFormat) -> Result<Vec<Vec<u8>>> {
         TextSignFormat::Ed25519 => Ed25519Signer::generate()?,
     };
     Ok(key)
-
 }
 
 impl TestSign for Blake3 {
@@ -141,19 +146,19 @@ impl KeyLoader for Blake3 {
 
 impl KeyGenerator for Blake3 {
     fn generate() -> Result<Vec<Vec<u8>>> {
-        let key = [0u8;32];
-        let key = key.to_vec();
-        Ok(vec![key])
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Ok(vec![key.to_vec()])
     }
 }
 
-impl KeyGenerator for Ed25519Signer{
+impl KeyGenerator for Ed25519Signer {
     fn generate() -> Result<Vec<Vec<u8>>> {
         let mut csprng = OsRng;
         let sk: SigningKey = SigningKey::generate(&mut csprng);
         let pk = sk.verifying_key().to_bytes().to_vec();
         let sk = sk.to_bytes().to_vec();
-        Ok(vec![sk,pk])
+        Ok(vec![sk, pk])
     }
 }
 
@@ -163,10 +168,11 @@ impl Blake3 {
     }
 
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
+        let key = key
+            .get(..32)
+            .ok_or_else(|| anyhow!("Key must be 32 bytes"))?;
         let key = key.try_into()?;
-        let signer = Blake3 { key };
-        Ok(signer)
+        Ok(Self::new(key))
     }
 }
 
@@ -192,7 +198,6 @@ impl Ed25519Verifier {
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -215,4 +220,4 @@ mod tests {
         assert!(pk.verify(&mut &data[..], &sig)?);
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,12 +1,34 @@
-use std::fs::File;
-use std::io::Read;
 use anyhow::Error;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
 
-pub fn get_reader(input: &str) -> Result<Box<dyn Read>,Error> {
+pub fn get_reader(input: &str) -> Result<Box<dyn Read>, Error> {
     let reader: Box<dyn Read> = if input == "-" {
         Box::new(std::io::stdin())
     } else {
         Box::new(File::open(input)?)
     };
     Ok(reader)
-}
\ No newline at end of file
+}
+
+/// Like `get_reader`, but the result can be rewound and read again. Stdin is
+/// spooled to a temporary file first.
+pub fn get_rewindable_reader(input: &str) -> Result<File, Error> {
+    if input == "-" {
+        let mut file = tempfile::tempfile()?;
+        std::io::copy(&mut std::io::stdin().lock(), &mut file)?;
+        file.rewind()?;
+        Ok(file)
+    } else {
+        Ok(File::open(input)?)
+    }
+}
+
+pub fn get_writer(output: &str) -> Result<Box<dyn Write + Send>, Error> {
+    let writer: Box<dyn Write + Send> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    Ok(writer)
+}